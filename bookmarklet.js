@@ -0,0 +1,10 @@
+// sends the current page to `refhelper serve`, served ready to drag at /bookmarklet,
+// every statement ends with `;` since lines are joined into one
+(function () {
+  var server = 'http://127.0.0.1:8080';
+  var page = {url: location.href, html: document.documentElement.outerHTML, pdf: true};
+  fetch(server + '/capture', {method: 'POST', headers: {'Content-Type': 'text/plain'}, body: JSON.stringify(page)})
+    .then(function (res) { return res.json(); })
+    .then(function (entry) { alert(entry.error ? 'refhelper: ' + entry.error : 'refhelper: added ' + entry.name + (entry.warning ? ', ' + entry.warning : '')); })
+    .catch(function (e) { alert('refhelper: ' + e); });
+})();
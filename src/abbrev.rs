@@ -0,0 +1,231 @@
+// abbreviation of journal and conference names,
+// full names in a mapping file are looked up first, ISO 4 word rules are the fallback
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+const LTWA: &str = include_str!("../abbrevs/ltwa.txt");
+const VENUES: &str = include_str!("../abbrevs/venues.txt");
+
+// articles, prepositions and conjunctions are omitted in ISO 4
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "at", "by", "for", "from", "in", "of", "on", "the", "to", "with", "de",
+    "des", "du", "et", "la", "le", "les", "der", "die", "das", "und", "für",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AbbrevMode {
+    Iso4,   // mapping file, bundled venues and word rules
+    Custom, // mapping file only
+}
+
+impl AbbrevMode {
+    pub const NAMES: &'static [&'static str] = &["iso4", "custom"];
+}
+
+impl FromStr for AbbrevMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "iso4" => Ok(AbbrevMode::Iso4),
+            "custom" => Ok(AbbrevMode::Custom),
+            _ => Err(format!(
+                "unknown abbreviation '{}', expect iso4 or custom",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Abbreviations {
+    venues: Vec<(String, String)>,      // full name -> abbreviation
+    words: Vec<(String, String, bool)>, // word -> abbreviation, is prefix
+    expand: bool,
+}
+
+impl Abbreviations {
+    // user mapping has lines like `Full Name = Abbr.`
+    pub fn load(mode: AbbrevMode, mapping: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let mut abbrevs = Abbreviations::default();
+        if let Some(p) = mapping {
+            abbrevs.venues = parse_mapping(&fs::read_to_string(p)?);
+        }
+        if mode == AbbrevMode::Iso4 {
+            abbrevs.venues.extend(parse_mapping(VENUES));
+            abbrevs.words = parse_words(LTWA);
+        }
+        Ok(abbrevs)
+    }
+
+    // reverse lookup, only names in mapping files can be expanded
+    pub fn expansions(mapping: Option<&Path>) -> Result<Self, Box<dyn Error>> {
+        let mut abbrevs = Abbreviations::load(AbbrevMode::Iso4, mapping)?;
+        abbrevs.words.clear();
+        abbrevs.expand = true;
+        Ok(abbrevs)
+    }
+
+    // whole name from mapping files, or abbreviated word by word
+    pub fn rewrite(&self, name: &str) -> String {
+        self.lookup(name)
+            .unwrap_or_else(|| self.abbreviate_words(name))
+    }
+
+    // whole name found in mapping files, abbreviated or expanded
+    pub fn lookup(&self, name: &str) -> Option<String> {
+        let key = venue_key(name);
+        let found = match self.expand {
+            true => self
+                .venues
+                .iter()
+                .find(|(f, a)| venue_key(a) == key && venue_key(f) != key)
+                .map(|(f, _)| f),
+            false => self
+                .venues
+                .iter()
+                .find(|(f, _)| venue_key(f) == key)
+                .map(|(_, a)| a),
+        };
+        found.cloned()
+    }
+
+    fn abbreviate_words(&self, name: &str) -> String {
+        let words: Vec<&str> = name.split_whitespace().collect();
+        // single word titles are never abbreviated
+        if self.words.is_empty() || words.len() < 2 {
+            return String::from(name);
+        }
+        words
+            .iter()
+            .enumerate()
+            .filter(|(i, w)| *i == 0 || !STOP_WORDS.contains(&w.to_lowercase().as_str()))
+            .map(|(_, w)| self.abbreviate_word(w))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    fn abbreviate_word(&self, word: &str) -> String {
+        // keep acronyms, numbers and words like "31st"
+        if word.chars().any(|c| c.is_ascii_digit())
+            || word.chars().filter(|c| c.is_uppercase()).count() > 1
+        {
+            return String::from(word);
+        }
+        let end = word.trim_end_matches(|c: char| !c.is_alphanumeric());
+        let (core, tail) = word.split_at(end.len());
+        let lower = core.to_lowercase();
+        // exact words are preferred to prefixes, longer prefixes to shorter ones
+        let rule = self
+            .words
+            .iter()
+            .filter(|(w, _, prefix)| *w == lower || (*prefix && lower.starts_with(w.as_str())))
+            .max_by_key(|(w, _, prefix)| (!prefix, w.len()));
+        let abbr = match rule {
+            Some((_, a, _)) if a != "n.a." => a,
+            _ => return String::from(word),
+        };
+        // "Proc.," is written as "Proc.,"; a dot is not doubled
+        let tail = if abbr.ends_with('.') {
+            tail.trim_start_matches('.')
+        } else {
+            tail
+        };
+        let mut chars = abbr.chars();
+        let first = chars.next().unwrap_or_default();
+        let first = match core.chars().next() {
+            Some(c) if c.is_uppercase() => first.to_uppercase().collect::<String>(),
+            _ => first.to_string(),
+        };
+        format!("{}{}{}", first, chars.as_str(), tail)
+    }
+}
+
+// case, dots and spaces do not matter in lookup
+fn venue_key(name: &str) -> String {
+    name.split(|c: char| c.is_whitespace() || c == '.')
+        .filter(|w| !w.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn parse_mapping(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter_map(|l| l.split_once('='))
+        .map(|(f, a)| (String::from(f.trim()), String::from(a.trim())))
+        .collect()
+}
+
+// lines of the LTWA are `WORDS;ABBREVIATIONS;LANGUAGES`, after a header
+fn parse_words(text: &str) -> Vec<(String, String, bool)> {
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#') && !l.starts_with("WORDS;"))
+        .filter_map(|l| {
+            let mut columns = l.split(';').map(|c| c.trim().to_lowercase());
+            let (word, abbr) = (columns.next()?, columns.next()?);
+            match word.strip_suffix('-') {
+                Some(p) => Some((String::from(p), abbr, true)),
+                None => Some((word, abbr, false)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_abbreviate() {
+        let abbrevs = Abbreviations::load(AbbrevMode::Iso4, None).unwrap();
+        assert_eq!(
+            abbrevs.rewrite(
+                "Proceedings of the 2013 ACM SIGCOMM Conference on Computer Communications"
+            ),
+            "Proc. 2013 ACM SIGCOMM Conf. Comput. Commun."
+        );
+        assert_eq!(
+            abbrevs.rewrite("Journal of Network and Systems Management"),
+            "J. Netw. Syst. Manag."
+        );
+        assert_eq!(abbrevs.rewrite("Communications of the ACM"), "Commun. ACM");
+        assert_eq!(abbrevs.rewrite("Networks"), "Networks");
+        // words which are not abbreviated
+        assert_eq!(
+            abbrevs.rewrite("IEEE Journal of Solid-State Circuits"),
+            "IEEE J. Solid-State Circuits"
+        );
+        assert_eq!(
+            abbrevs.rewrite("ACM Transactions on Algorithms"),
+            "ACM Trans. Algorithms"
+        );
+        assert_eq!(
+            abbrevs.rewrite("IEEE Transactions on Automatic Control"),
+            "IEEE Trans. Autom. Control"
+        );
+        assert_eq!(
+            abbrevs.rewrite("Pattern Recognition Letters"),
+            "Pattern Recognit. Lett."
+        );
+        assert_eq!(
+            abbrevs.rewrite("Workshop on Hot Topics in Networks"),
+            "Workshop Hot Top. Netw."
+        );
+        assert_eq!(
+            abbrevs.rewrite("Journal of Modeling and Simulation"),
+            "J. Model. Simul."
+        );
+        let expansions = Abbreviations::expansions(None).unwrap();
+        assert_eq!(
+            expansions.rewrite("IEEE/ACM Trans Netw"),
+            "IEEE/ACM Transactions on Networking"
+        );
+        assert_eq!(expansions.rewrite("Unknown Conf."), "Unknown Conf.");
+    }
+}
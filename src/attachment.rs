@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::files::{self, FileMeta};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachmentKind {
+    Pdf,
+    Supplement,
+    Slides,
+    Code,
+    Dataset,
+}
+
+impl AttachmentKind {
+    pub const NAMES: &'static [&'static str] = &["pdf", "supplement", "slides", "code", "dataset"];
+    // with aliases
+    pub const VALUES: &'static [&'static str] = &[
+        "pdf",
+        "supplement",
+        "slides",
+        "code",
+        "dataset",
+        "supp",
+        "data",
+    ];
+}
+
+impl FromStr for AttachmentKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pdf" => Ok(AttachmentKind::Pdf),
+            "supplement" | "supp" => Ok(AttachmentKind::Supplement),
+            "slides" => Ok(AttachmentKind::Slides),
+            "code" => Ok(AttachmentKind::Code),
+            "dataset" | "data" => Ok(AttachmentKind::Dataset),
+            _ => Err(format!(
+                "unknown attachment kind '{}', expect pdf, supplement, slides, code or dataset",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AttachmentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AttachmentKind::Pdf => "pdf",
+            AttachmentKind::Supplement => "supplement",
+            AttachmentKind::Slides => "slides",
+            AttachmentKind::Code => "code",
+            AttachmentKind::Dataset => "dataset",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Target {
+    File(PathBuf),
+    Url(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub kind: AttachmentKind,
+    pub target: Target,
+    #[serde(default)]
+    pub meta: Option<FileMeta>,
+}
+
+impl Attachment {
+    // target starting with http(s):// is an url, otherwise a local file
+    pub fn new(kind: AttachmentKind, target: &str) -> Attachment {
+        if target.starts_with("http://") || target.starts_with("https://") {
+            Attachment {
+                kind,
+                target: Target::Url(String::from(target)),
+                meta: None,
+            }
+        } else {
+            Attachment::from_file(kind, PathBuf::from(target))
+        }
+    }
+
+    pub fn from_file(kind: AttachmentKind, path: PathBuf) -> Attachment {
+        // record hash and size to detect changed or moved files later
+        let meta = FileMeta::from_path(&path).ok();
+        Attachment {
+            kind,
+            target: Target::File(path),
+            meta,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        match &self.target {
+            Target::File(p) => Some(p),
+            Target::Url(_) => None,
+        }
+    }
+
+    pub fn relink(&mut self, path: PathBuf) {
+        *self = Attachment::from_file(self.kind, path);
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.path().is_some_and(|p| !p.exists())
+    }
+
+    // what to pass to the external viewer
+    pub fn location(&self) -> String {
+        match &self.target {
+            Target::File(p) => p.display().to_string(),
+            Target::Url(u) => u.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Attachment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.location())?;
+        if let Some(p) = self.path() {
+            if files::check_file(p) == files::FileStatus::Missing {
+                write!(f, " (missing)")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+// self-contained zip archive of some entries: a .bib file, the library subset
+// as JSON and their attached files, referenced by paths relative to the archive
+use serde_json::Map;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::attachment::Target;
+use crate::files;
+use crate::schema::SCHEMA_VERSION;
+use crate::storage;
+use crate::Entry;
+
+const BIB_FILE: &str = "references.bib";
+const LIB_FILE: &str = "library.json";
+const FILES_DIR: &str = "files";
+
+// returns the number of files put into archive
+pub fn write_bundle(out: &Path, entries: &[&Entry], bibtex: &str) -> Result<usize, Box<dyn Error>> {
+    let mut zip = ZipWriter::new(File::create(out)?);
+    let options = FileOptions::default();
+    let mut names = HashSet::new();
+    let mut bundled = Vec::new();
+    for entry in entries {
+        let mut entry: Entry = (*entry).clone();
+        for attachment in entry.attachments.iter_mut() {
+            let path = match attachment.path() {
+                Some(p) if p.is_file() => p.to_path_buf(),
+                _ => continue,
+            };
+            // files/<citekey>/<file name>, numbered if the name is taken
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            let mut name = format!("{}/{}/{}", FILES_DIR, entry.name, file_name);
+            let mut n = 1;
+            while !names.insert(name.clone()) {
+                n += 1;
+                name = format!("{}/{}/{}-{}", FILES_DIR, entry.name, n, file_name);
+            }
+            zip.start_file(name.as_str(), options)?;
+            io::copy(&mut File::open(&path)?, &mut zip)?;
+            attachment.target = Target::File(PathBuf::from(name));
+        }
+        bundled.push(entry);
+    }
+    zip.start_file(BIB_FILE, options)?;
+    zip.write_all(bibtex.as_bytes())?;
+    zip.start_file(LIB_FILE, options)?;
+    storage::write_json_to(&mut zip, SCHEMA_VERSION, &bundled, &Map::new())?;
+    zip.finish()?;
+    Ok(names.len())
+}
+
+// extract files of archive into folder, and return its entries
+// with attachments pointing to the extracted files
+pub fn read_bundle(path: &Path, folder: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut zip = ZipArchive::new(File::open(path)?)?;
+    let lib = storage::read_json_from(zip.by_name(LIB_FILE)?)?;
+    // files extracted under another name, since a different file has their name
+    let mut renamed: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for i in 0..zip.len() {
+        let mut file = zip.by_index(i)?;
+        // skip names like "../x" escaping the folder
+        let name = match file.enclosed_name() {
+            Some(n) if n.starts_with(FILES_DIR) && file.is_file() => n.to_path_buf(),
+            _ => continue,
+        };
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let target = folder.join(&name);
+        if target.exists() {
+            let hash = hex::encode(Sha256::digest(&data));
+            if files::sha256_file(&target)? == hash {
+                continue;
+            }
+            let free = free_name(&target, &hash);
+            println!(
+                "{} exists with other content, extracted to {}",
+                target.display(),
+                free.display()
+            );
+            if !free.exists() {
+                fs::write(&free, &data)?;
+            }
+            renamed.insert(name, free);
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&target, &data)?;
+    }
+
+    let mut entries = lib
+        .entries
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<Vec<Entry>, _>>()?;
+    for attachment in entries.iter_mut().flat_map(|e| e.attachments.iter_mut()) {
+        if let Some(p) = attachment.path().filter(|p| p.is_relative()) {
+            let p = renamed.get(p).cloned().unwrap_or_else(|| folder.join(p));
+            attachment.target = Target::File(p.canonicalize().unwrap_or(p));
+        }
+    }
+    Ok(entries)
+}
+
+// "swan.pdf" -> "swan-2.pdf", the first number not taken or taken by the same content
+fn free_name(path: &Path, hash: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()));
+    (2..)
+        .map(|n| path.with_file_name(format!("{}-{}{}", stem, n, ext.as_deref().unwrap_or(""))))
+        .find(|p| !p.exists() || files::sha256_file(p).is_ok_and(|h| h == hash))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::attachment::{Attachment, AttachmentKind};
+
+    #[test]
+    fn test_bundle() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("refhelper-bundle-{}", std::process::id()));
+        fs::create_dir_all(dir.join("out"))?;
+        let pdf = dir.join("swan.pdf");
+        fs::write(&pdf, b"%PDF-1.4 test")?;
+        let mut entry = Entry::new("swan", "10.1145/2486001.2486012");
+        entry.attach(Attachment::from_file(AttachmentKind::Pdf, pdf));
+        entry.attach(Attachment::new(
+            AttachmentKind::Code,
+            "https://github.com/x/y",
+        ));
+        let zip = dir.join("b.zip");
+        assert_eq!(write_bundle(&zip, &[&entry], "@article{swan,}")?, 1);
+
+        let entries = read_bundle(&zip, &dir.join("out"))?;
+        let path = entries[0].attachments[0].path().unwrap().to_path_buf();
+        assert!(path.ends_with("out/files/swan/swan.pdf"));
+        assert_eq!(fs::read(path)?, b"%PDF-1.4 test");
+        // the same file is linked again, another one is not overwritten
+        let entries = read_bundle(&zip, &dir.join("out"))?;
+        assert!(entries[0].attachments[0]
+            .path()
+            .unwrap()
+            .ends_with("swan.pdf"));
+        fs::write(dir.join("out/files/swan/swan.pdf"), b"%PDF-1.4 other")?;
+        let entries = read_bundle(&zip, &dir.join("out"))?;
+        let path = entries[0].attachments[0].path().unwrap().to_path_buf();
+        assert!(path.ends_with("out/files/swan/swan-2.pdf"));
+        assert_eq!(fs::read(path)?, b"%PDF-1.4 test");
+        let entries = read_bundle(&zip, &dir.join("out"))?;
+        assert!(entries[0].attachments[0]
+            .path()
+            .unwrap()
+            .ends_with("swan-2.pdf"));
+        assert_eq!(
+            entries[0].attachments[1].location(),
+            "https://github.com/x/y"
+        );
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -1,9 +1,23 @@
 use clap::AppSettings;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::env;
 use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::iter::Peekable;
 use std::path::PathBuf;
+use std::str::Chars;
 use structopt::StructOpt;
 
+use crate::abbrev::AbbrevMode;
+use crate::attachment::AttachmentKind;
+use crate::config;
+use crate::files;
+use crate::lint::LintOptions;
+use crate::normalize::{GenOptions, Mode, Protect};
+use crate::record::{ListOptions, Output};
+use crate::render::{OutputFormat, STYLE_NAMES};
 use crate::rustyline;
 use crate::utils;
 use crate::Library;
@@ -32,15 +46,28 @@ pub enum Command {
     /// List entries of current library
     #[structopt(name = "list", alias = "ls")]
     #[structopt(settings(CLI_CLAP_SETTINGS))]
-    List,
+    List {
+        #[structopt(flatten)]
+        opts: ListOptions,
+    },
 
     /// Search entries with some pattern
     #[structopt(name = "search", alias = "s")]
     #[structopt(settings(CLI_CLAP_SETTINGS))]
     Search {
-        pat: String,
+        /// Words to match, e.g. `author:smith year:2013 network`
+        #[structopt(required = true)]
+        pat: Vec<String>,
         #[structopt(short, long)]
         fuzzy: bool,
+        /// Match the pattern as exact substring of bibtex, the search before ranking
+        #[structopt(short, long, conflicts_with = "fuzzy")]
+        exact: bool,
+        /// Search text of linked pdf files
+        #[structopt(long)]
+        fulltext: bool,
+        #[structopt(flatten)]
+        opts: ListOptions,
     },
 
     /// Add an entry to current library using DOI or arXiv id
@@ -72,6 +99,22 @@ pub enum Command {
         path: PathBuf,
     },
 
+    /// Import items of a Zotero library (from its zotero.sqlite)
+    #[structopt(name = "import-zotero")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    ImportZotero {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+
+    /// Merge entries of another library into current library
+    #[structopt(name = "merge")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Merge {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+    },
+
     /// Delete an entry in current library
     #[structopt(name = "del", alias = "rm")]
     #[structopt(settings(CLI_CLAP_SETTINGS))]
@@ -82,15 +125,106 @@ pub enum Command {
     #[structopt(settings(CLI_CLAP_SETTINGS))]
     Link { id: usize, path: PathBuf },
 
-    /// View chosen pdf file in pdfviewer
+    /// Attach a file or url (pdf, supplement, slides, code, dataset) to an entry
+    #[structopt(name = "attach")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Attach {
+        id: usize,
+        /// Kind of attachment (pdf, supplement, slides, code, dataset)
+        #[structopt(possible_values = AttachmentKind::VALUES, case_insensitive = true)]
+        kind: AttachmentKind,
+        target: String,
+    },
+
+    /// Remove n-th attachment of an entry
+    #[structopt(name = "detach")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Detach { id: usize, n: usize },
+
+    /// View pdf file or n-th attachment of an entry
     #[structopt(name = "view")]
     #[structopt(settings(CLI_CLAP_SETTINGS))]
-    View { id: usize },
+    View { id: usize, n: Option<usize> },
+
+    /// Show all fields, tags, attachments and the note of an entry
+    #[structopt(name = "show")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Show {
+        id: usize,
+        /// Output format (table, json, jsonl, csv, tsv), default is `--output` of cli
+        #[structopt(long, possible_values = Output::NAMES)]
+        output: Option<Output>,
+    },
+
+    /// Edit bibtex of an entry in $EDITOR
+    #[structopt(name = "edit")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Edit { id: usize },
+
+    /// Check linked pdf files and repair moved ones
+    #[structopt(name = "check-files")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    CheckFiles {
+        #[structopt(parse(from_os_str))]
+        folder: Option<PathBuf>,
+        /// Relink entries with identical pdf files to the same file
+        #[structopt(long)]
+        dedup: bool,
+    },
 
     /// Generate bibtex file of one entry or entire library
     #[structopt(name = "gen")]
     #[structopt(settings(CLI_CLAP_SETTINGS))]
-    Gen { id: Option<usize> },
+    Gen {
+        id: Option<usize>,
+        #[structopt(flatten)]
+        opts: GenOptions,
+    },
+
+    /// Check entries for missing fields, bad DOIs, citekeys and titles
+    #[structopt(name = "lint")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Lint {
+        #[structopt(flatten)]
+        opts: LintOptions,
+    },
+
+    /// Render references of entries in a citation style
+    #[structopt(name = "cite", alias = "render")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Cite {
+        /// Entries to render, all entries if not given
+        ids: Vec<usize>,
+        /// Render entries matching this search query instead
+        #[structopt(short, long)]
+        search: Option<String>,
+        /// Builtin style (apa, ieee, acm, chicago) or path of a CSL file
+        #[structopt(long, default_value = "apa")]
+        style: String,
+        /// Output format (text, markdown, html, rtf)
+        #[structopt(long, default_value = "text")]
+        #[structopt(possible_values = OutputFormat::VALUES, case_insensitive = true)]
+        format: OutputFormat,
+    },
+
+    /// Export entries matching a query, e.g. `tag:reading`, or `*` for all, with their files as a zip archive
+    #[structopt(name = "bundle")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Bundle {
+        query: String,
+        #[structopt(parse(from_os_str))]
+        out: PathBuf,
+    },
+
+    /// Import entries and files of a bundle, files are extracted to folder
+    #[structopt(name = "unbundle")]
+    #[structopt(settings(CLI_CLAP_SETTINGS))]
+    Unbundle {
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+        #[structopt(parse(from_os_str))]
+        folder: Option<PathBuf>,
+    },
 
     /// Quit from interactive CLI
     #[structopt(name = "quit", alias = "exit")]
@@ -98,20 +232,200 @@ pub enum Command {
     Quit,
 }
 
+// what an argument of a command takes, for completion in the shell
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    Entry,
+    Path,
+    // search query, where `tag:` takes a tag
+    Query,
+    Values(&'static [&'static str]),
+    Other,
+}
+
+// long name, short name, and kind of its value, None for flags
+pub type Opt = (&'static str, Option<char>, Option<ArgKind>);
+
+// arguments of a command in `Command`, kept in step with it by `test_command_args`
+pub struct CommandArgs {
+    pub names: &'static [&'static str],
+    pub opts: &'static [&'static [Opt]],
+    // kinds of positional arguments, the last one takes many values if `repeated`
+    pub positionals: &'static [ArgKind],
+    pub repeated: bool,
+}
+
+const LIST_OPTS: &[Opt] = &[
+    ("output", None, Some(ArgKind::Values(Output::NAMES))),
+    ("columns", None, Some(ArgKind::Other)),
+    ("sort", None, Some(ArgKind::Other)),
+    ("limit", None, Some(ArgKind::Other)),
+    ("offset", None, Some(ArgKind::Other)),
+];
+
+const GEN_OPTS: &[Opt] = &[
+    ("normalize", None, None),
+    ("mode", None, Some(ArgKind::Values(Mode::NAMES))),
+    ("utf8", None, None),
+    ("protect", None, Some(ArgKind::Values(Protect::NAMES))),
+    ("fields", None, Some(ArgKind::Other)),
+    ("exclude", None, Some(ArgKind::Other)),
+    ("abbrev", None, Some(ArgKind::Values(AbbrevMode::NAMES))),
+    ("expand", None, None),
+    ("abbrev-file", None, Some(ArgKind::Path)),
+];
+
+const LINT_OPTS: &[Opt] = &[
+    ("json", None, None),
+    ("fix", None, None),
+    ("online", None, None),
+    ("template", None, Some(ArgKind::Other)),
+];
+
+const fn args(
+    names: &'static [&'static str],
+    opts: &'static [&'static [Opt]],
+    positionals: &'static [ArgKind],
+) -> CommandArgs {
+    CommandArgs {
+        names,
+        opts,
+        positionals,
+        repeated: false,
+    }
+}
+
+pub const COMMAND_ARGS: &[CommandArgs] = &[
+    args(&["open"], &[], &[ArgKind::Path]),
+    args(&["list", "ls"], &[LIST_OPTS], &[]),
+    CommandArgs {
+        names: &["search", "s"],
+        opts: &[
+            &[
+                ("fuzzy", Some('f'), None),
+                ("exact", Some('e'), None),
+                ("fulltext", None, None),
+            ],
+            LIST_OPTS,
+        ],
+        positionals: &[ArgKind::Query],
+        repeated: true,
+    },
+    args(&["add"], &[], &[ArgKind::Other, ArgKind::Other]),
+    args(&["add_batch"], &[], &[ArgKind::Path]),
+    args(&["download"], &[], &[ArgKind::Path]),
+    args(&["load"], &[], &[ArgKind::Path]),
+    args(&["import-zotero"], &[], &[ArgKind::Path]),
+    args(&["merge"], &[], &[ArgKind::Path]),
+    args(&["del", "rm"], &[], &[ArgKind::Entry]),
+    args(&["link"], &[], &[ArgKind::Entry, ArgKind::Path]),
+    args(
+        &["attach"],
+        &[],
+        &[
+            ArgKind::Entry,
+            ArgKind::Values(AttachmentKind::NAMES),
+            ArgKind::Path,
+        ],
+    ),
+    args(&["detach"], &[], &[ArgKind::Entry, ArgKind::Other]),
+    args(&["view"], &[], &[ArgKind::Entry, ArgKind::Other]),
+    args(
+        &["show"],
+        &[&[("output", None, Some(ArgKind::Values(Output::NAMES)))]],
+        &[ArgKind::Entry],
+    ),
+    args(&["edit"], &[], &[ArgKind::Entry]),
+    args(
+        &["check-files"],
+        &[&[("dedup", None, None)]],
+        &[ArgKind::Path],
+    ),
+    args(&["gen"], &[GEN_OPTS], &[ArgKind::Entry]),
+    args(&["lint"], &[LINT_OPTS], &[]),
+    CommandArgs {
+        names: &["cite", "render"],
+        opts: &[&[
+            ("search", Some('s'), Some(ArgKind::Query)),
+            ("style", None, Some(ArgKind::Values(STYLE_NAMES))),
+            ("format", None, Some(ArgKind::Values(OutputFormat::NAMES))),
+        ]],
+        positionals: &[ArgKind::Entry],
+        repeated: true,
+    },
+    args(&["bundle"], &[], &[ArgKind::Query, ArgKind::Path]),
+    args(&["unbundle"], &[], &[ArgKind::Path, ArgKind::Path]),
+    args(&["quit", "exit"], &[], &[]),
+];
+
+// how `cli` reads commands, when not typed in
+#[derive(StructOpt, Debug, Default)]
+pub struct ShellOptions {
+    /// Run commands of a file, stdin is read when it is not a terminal
+    #[structopt(long, parse(from_os_str))]
+    pub script: Option<PathBuf>,
+    /// Stop a script at the first failed command, exit with 1
+    #[structopt(long)]
+    pub stop_on_error: bool,
+    /// Output format of list and search (table, json, jsonl, csv, tsv)
+    #[structopt(long, default_value = "table", possible_values = Output::NAMES)]
+    pub output: Output,
+}
+
+enum Flow {
+    Continue,
+    Quit,
+}
+
 fn welcome() {
     println!("Welcome to refhelper {}!", env!("CARGO_PKG_VERSION"));
 }
 
-pub fn loop_run(libpath: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
-    welcome();
-    // check if library is specified
-    let mut lib = match libpath {
+pub fn loop_run(libpath: Option<PathBuf>, opts: &ShellOptions) -> Result<(), Box<dyn Error>> {
+    let config = config::load()?;
+    // check if library is specified, or set in config
+    let mut lib = match libpath.or_else(|| config.library.clone()) {
         Some(path) => Library::from_path(path)?,
         None => Library::default(),
     };
-    let mut rl = rustyline::my_editor();
+    let result = match &opts.script {
+        Some(path) => {
+            let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            let name = path.display().to_string();
+            run_script(&mut lib, BufReader::new(file), &name, opts)
+        }
+        None if !io::stdin().is_terminal() => {
+            run_script(&mut lib, io::stdin().lock(), "stdin", opts)
+        }
+        None => {
+            welcome();
+            run_interactive(&mut lib, opts)
+        }
+    };
+    // a script stopped at a failed command leaves the library as it was
+    if let (Err(e), true) = (&result, opts.stop_on_error) {
+        return Err(format!("{}, changes are not saved", e).into());
+    }
+    match (result, lib.save()) {
+        (Err(e), Err(save)) => Err(format!("{}, and save failed, error {}", e, save).into()),
+        (result, saved) => saved.and(result),
+    }
+}
+
+fn run_interactive(lib: &mut Library, opts: &ShellOptions) -> Result<(), Box<dyn Error>> {
+    let mut rl = rustyline::my_editor(COMMAND_ARGS);
+    let mut history = history_path(lib);
+    if let Some(p) = &history {
+        // no history yet for new libraries
+        let _ = rl.load_history(p);
+    }
+    let mut vars = HashMap::new();
     let prompt = ">> ";
     loop {
+        if let Some(helper) = rl.helper_mut() {
+            let (entries, tags) = completion_entries(lib);
+            helper.set_entries(entries, tags);
+        }
         let readline = rl.readline(prompt);
         let line = match readline {
             Ok(line) if line.trim() == "" => continue,
@@ -120,34 +434,370 @@ pub fn loop_run(libpath: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
             Err(rustyline::ReadlineError::Eof) => break,
             Err(_) => String::from("help"),
         };
-        match Command::from_iter_safe(line.trim().split_whitespace()) {
-            Ok(Command::Open { path }) => lib = Library::from_path(path)?,
-            Ok(Command::Quit) => break,
-            Ok(command) => execute_command(&mut lib, command),
-            Err(e) => println!("{}", e.message),
-        };
+        let flow = run_line(lib, &mut vars, &line, opts.output);
         rl.add_history_entry(line);
+        match flow {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(e) => eprintln!("{}", e),
+        }
+        // another library is opened
+        if history_path(lib) != history {
+            save_history(&mut rl, &history);
+            rl.clear_history();
+            history = history_path(lib);
+            if let Some(p) = &history {
+                let _ = rl.load_history(p);
+            }
+        }
     }
-    lib.save()?;
+    save_history(&mut rl, &history);
     Ok(())
 }
 
-fn execute_command(lib: &mut Library, command: Command) {
+// run commands line by line, failed ones are reported with their line numbers
+fn run_script(
+    lib: &mut Library,
+    reader: impl BufRead,
+    name: &str,
+    opts: &ShellOptions,
+) -> Result<(), Box<dyn Error>> {
+    let mut vars = HashMap::new();
+    for (n, line) in reader.lines().enumerate() {
+        match run_line(lib, &mut vars, &line?, opts.output) {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => break,
+            Err(e) => {
+                eprintln!("{}:{}: {}", name, n + 1, e);
+                if opts.stop_on_error {
+                    return Err(format!("{} stopped at line {}", name, n + 1).into());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// a command, or `name=value` setting a variable used as `$name` later
+fn run_line(
+    lib: &mut Library,
+    vars: &mut HashMap<String, String>,
+    line: &str,
+    output: Output,
+) -> Result<Flow, String> {
+    let words = split_line(line, vars)?;
+    if let [word] = &words[..] {
+        if let Some((name, value)) = word.split_once('=').filter(|(n, _)| is_var_name(n)) {
+            vars.insert(name.to_owned(), value.to_owned());
+            return Ok(Flow::Continue);
+        }
+    }
+    if words.is_empty() {
+        return Ok(Flow::Continue);
+    }
+    match Command::from_iter_safe(words) {
+        Ok(Command::Open { path }) => {
+            *lib =
+                Library::from_path(path).map_err(|e| format!("Open library failed, error {}", e))?
+        }
+        Ok(Command::Quit) => return Ok(Flow::Quit),
+        Ok(command) => execute_command(lib, command, output)?,
+        Err(e) if e.kind == clap::ErrorKind::HelpDisplayed => println!("{}", e.message),
+        Err(e) => return Err(e.message),
+    };
+    Ok(Flow::Continue)
+}
+
+// commands are remembered per library in the state dir, keyed by its canonical path,
+// e.g. /home/me/refs.json -> history/refs-<hash>.history
+fn history_path(lib: &Library) -> Option<PathBuf> {
+    let path = files::canonical(lib.path.as_ref()?);
+    let hash = hex::encode(Sha256::digest(path.to_string_lossy().as_bytes()));
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{}-{}.history", stem, &hash[..16]);
+    Some(config::state_dir()?.join("history").join(name))
+}
+
+fn save_history(rl: &mut rustyline::Editor<rustyline::RLHelper>, path: &Option<PathBuf>) {
+    if let Some(p) = path {
+        let saved = p
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .map_err(|e| e.into())
+            .and_then(|_| rl.save_history(p));
+        if let Err(e) = saved {
+            println!("save history failed, error {}", e);
+        }
+    }
+}
+
+// ids, citekeys and titles of entries, and all tags, completed in the shell
+fn completion_entries(lib: &Library) -> (Vec<(usize, String, String)>, Vec<String>) {
+    let entries = lib.entries.iter().enumerate();
+    let entries = entries.map(|(id, e)| (id, e.name.clone(), e.title.clone()));
+    let mut tags: Vec<_> = lib.entries.iter().flat_map(|e| e.tags()).collect();
+    tags.sort();
+    tags.dedup();
+    (entries.collect(), tags)
+}
+
+// fails on commands which cannot run or fail, so that scripts may stop on them
+fn execute_command(lib: &mut Library, command: Command, output: Output) -> Result<(), String> {
     if lib.path.is_none() {
-        println!("No library is open");
-        return;
+        return Err(String::from("No library is open"));
+    }
+    let ids = match &command {
+        Command::Del { id }
+        | Command::Link { id, .. }
+        | Command::Attach { id, .. }
+        | Command::Detach { id, .. }
+        | Command::View { id, .. }
+        | Command::Show { id, .. }
+        | Command::Edit { id }
+        | Command::Gen { id: Some(id), .. } => vec![*id],
+        Command::Cite { ids, .. } => ids.clone(),
+        _ => vec![],
+    };
+    if let Some(id) = ids.iter().find(|&&id| id >= lib.entries.len()) {
+        return Err(format!("No such id {}", id));
     }
     match command {
-        Command::List => lib.print(),
-        Command::Search { pat, fuzzy } => lib.search(&pat, fuzzy),
-        Command::Add { name, doi } => lib.add(&name, &doi),
-        Command::AddBatch { path } => lib.add_batch(utils::read_doi_file(path)),
-        Command::Load { path } => lib.load_bibtex(utils::read_bibtex_file(path)),
-        Command::Del { id } => lib.del(id),
-        Command::Link { id, path } => lib.link(id, path),
-        Command::View { id } => lib.view(id),
-        Command::Gen { id } => lib.gen_bibtex(id),
-        Command::Download { folder } => lib.download(folder),
+        Command::List { mut opts } => {
+            opts.output = opts.output.or(Some(output));
+            lib.print(&opts)
+        }
+        Command::Search {
+            pat,
+            fuzzy,
+            exact,
+            fulltext,
+            mut opts,
+        } => {
+            opts.output = opts.output.or(Some(output));
+            lib.search(&pat.join(" "), fuzzy, exact, fulltext, &opts)
+        }
+        Command::Add { name, doi } => {
+            lib.try_add(&name, &doi)?;
+        }
+        Command::AddBatch { path } => lib.add_batch(utils::read_doi_file(path)?)?,
+        Command::Load { path } => lib.load_bibtex(utils::read_bibtex_file(path)?),
+        Command::ImportZotero { path } => lib.import_zotero(path)?,
+        Command::Merge { path } => lib.merge_from(path)?,
+        Command::Del { id } => lib.del(id)?,
+        Command::Link { id, path } => lib.link(id, path)?,
+        Command::Attach { id, kind, target } => lib.attach(id, kind, &target)?,
+        Command::Detach { id, n } => lib.detach(id, n)?,
+        Command::View { id, n } => lib.view(id, n)?,
+        Command::Show { id, output: o } => lib.show(id, o.unwrap_or(output))?,
+        Command::Edit { id } => lib.edit(id)?,
+        Command::Gen { id, opts } => lib.gen_bibtex(id, &opts)?,
+        Command::Lint { opts } => {
+            lib.lint(&opts);
+        }
+        Command::Cite {
+            ids,
+            search,
+            style,
+            format,
+        } => lib.cite(ids, search, &style, format)?,
+        Command::Bundle { query, out } => lib.bundle(&query, out)?,
+        Command::Unbundle { path, folder } => lib.unbundle(path, folder)?,
+        Command::Download { folder } => lib.download(folder)?,
+        Command::CheckFiles { folder, dedup } => lib.check_files(folder, dedup),
         _ => {}
     };
+    Ok(())
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// split a line like a shell does: quotes keep spaces in a word, `\` escapes a character,
+// `#` starts a comment and `$name` or `${name}` is replaced by a variable of the script
+// or the environment, except in single quotes
+fn split_line(line: &str, vars: &HashMap<String, String>) -> Result<Vec<String>, String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+                continue;
+            }
+            '#' if !in_word => break,
+            '\'' => loop {
+                match chars.next() {
+                    Some('\'') => break,
+                    Some(c) => word.push(c),
+                    None => return Err(String::from("missing closing quote \'")),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some(c @ ('"' | '\\' | '$')) => word.push(c),
+                        Some(c) => word.extend(['\\', c]),
+                        None => return Err(String::from("missing closing quote \"")),
+                    },
+                    Some('$') => expand_var(&mut chars, vars, &mut word)?,
+                    Some(c) => word.push(c),
+                    None => return Err(String::from("missing closing quote \"")),
+                }
+            },
+            '\\' => word.extend(chars.next()),
+            '$' => expand_var(&mut chars, vars, &mut word)?,
+            c => word.push(c),
+        }
+        in_word = true;
+    }
+    if in_word {
+        words.push(word);
+    }
+    Ok(words)
+}
+
+// `chars` is right after `$`, a `$` not followed by a name is kept
+fn expand_var(
+    chars: &mut Peekable<Chars>,
+    vars: &HashMap<String, String>,
+    word: &mut String,
+) -> Result<(), String> {
+    let braced = chars.next_if_eq(&'{').is_some();
+    let mut name = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+        name.push(c);
+    }
+    if braced && chars.next() != Some('}') {
+        return Err(String::from("missing closing brace of ${"));
+    }
+    if name.is_empty() {
+        word.push('$');
+        return Ok(());
+    }
+    match vars.get(&name).cloned().or_else(|| env::var(&name).ok()) {
+        Some(value) => word.push_str(&value),
+        None => return Err(format!("undefined variable ${}", name)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_command_args() {
+        // every command and option of the table is known to the parser
+        for command in COMMAND_ARGS {
+            for name in command.names {
+                for opt in command.opts.iter().flat_map(|o| o.iter()) {
+                    let long = format!("--{}", opt.0);
+                    if let Err(e) = Command::from_iter_safe(&[*name, long.as_str()]) {
+                        assert_ne!(
+                            e.kind,
+                            clap::ErrorKind::UnknownArgument,
+                            "{} {}",
+                            name,
+                            long
+                        );
+                        assert_ne!(e.kind, clap::ErrorKind::UnrecognizedSubcommand, "{}", name);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_line() {
+        let mut vars = HashMap::new();
+        vars.insert(String::from("dir"), String::from("my papers"));
+        let split = |line| split_line(line, &vars);
+        assert_eq!(
+            split(r#"search "software defined" -f # fuzzy"#).unwrap(),
+            vec!["search", "software defined", "-f"]
+        );
+        assert_eq!(
+            split(r#"link 0 "$dir/a.pdf" '$dir' ${dir}\ x"#).unwrap(),
+            vec!["link", "0", "my papers/a.pdf", "$dir", "my papers x"]
+        );
+        assert_eq!(split(r#"add x "" "#).unwrap(), vec!["add", "x", ""]);
+        assert_eq!(split("  # comment").unwrap(), Vec::<String>::new());
+        assert!(split("search \"open").is_err());
+        assert!(split("link 0 $undefined_var_of_test").is_err());
+    }
+
+    #[test]
+    fn test_history_path() {
+        let mut lib = Library::default();
+        assert_eq!(history_path(&lib), None);
+        if config::state_dir().is_none() {
+            return;
+        }
+        let dir = env::temp_dir().join(format!("refhelper-history-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("other")).unwrap();
+        std::fs::write(dir.join("refs.json"), "").unwrap();
+        std::fs::write(dir.join("other").join("refs.json"), "").unwrap();
+        lib.path = Some(dir.join("refs.json"));
+        let history = history_path(&lib).unwrap();
+        assert!(history.starts_with(config::state_dir().unwrap()));
+        assert!(history
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("refs-"));
+        lib.path = Some(dir.join(".").join("refs.json"));
+        assert_eq!(history_path(&lib).unwrap(), history);
+        lib.path = Some(dir.join("other").join("refs.json"));
+        assert_ne!(history_path(&lib).unwrap(), history);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_script_errors() {
+        let mut lib = Library {
+            path: Some(PathBuf::from("refs.json")),
+            ..Library::default()
+        };
+        lib.entries.push(crate::Entry::new("rl", "1904.12901"));
+        let script = "detach 0 0\nload no-such-file.bib\ndel 0\n";
+        let mut opts = ShellOptions {
+            stop_on_error: true,
+            ..ShellOptions::default()
+        };
+        assert!(run_script(&mut lib, script.as_bytes(), "test", &opts).is_err());
+        assert_eq!(lib.entries.len(), 1);
+        opts.stop_on_error = false;
+        assert!(run_script(&mut lib, script.as_bytes(), "test", &opts).is_ok());
+        assert!(lib.entries.is_empty());
+    }
+
+    #[test]
+    fn test_script_not_saved() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("refhelper-script-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (path, script) = (dir.join("refs.json"), dir.join("script.txt"));
+        let json = r#"{"schema_version": 3, "entries": [{"uid": "a", "name": "rl", "doi": "1904.12901"}]}"#;
+        std::fs::write(&path, json)?;
+        std::fs::write(&script, "del 0\ndetach 0 0\n")?;
+        let opts = ShellOptions {
+            script: Some(script),
+            stop_on_error: true,
+            ..ShellOptions::default()
+        };
+        let err = loop_run(Some(path.clone()), &opts).unwrap_err();
+        assert!(err.to_string().contains("not saved"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&path)?, json);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
 }
@@ -0,0 +1,163 @@
+// user settings read from $XDG_CONFIG_HOME/refhelper/config.json
+// (~/.config/refhelper/config.json), every field may be left out
+use serde::Deserialize;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::lint;
+use crate::record::Column;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EditMode {
+    Emacs,
+    Vi,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub user_agent: String,
+    // seconds
+    pub timeout: Option<u64>,
+    // e.g. http://127.0.0.1:1080, socks is not supported
+    pub proxy: Option<String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            user_agent: String::from("hyper/0.5.2"),
+            timeout: None,
+            proxy: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    // opened when no library is given
+    pub library: Option<PathBuf>,
+    // command to open pdf files and urls, e.g. "zathura --fork"
+    pub viewer: Option<String>,
+    // where pdf files are downloaded, next to the library by default
+    pub download_dir: Option<PathBuf>,
+    // downloads and requests at the same time
+    pub concurrency: usize,
+    pub http: HttpConfig,
+    // citekeys of new entries, also checked by `lint` if set
+    pub citekey_template: Option<String>,
+    pub edit_mode: EditMode,
+    // columns of `list` and `search`, e.g. ["id", "author", "year", "title"]
+    pub columns: Option<Vec<Column>>,
+    // command paging long tables, $PAGER or less by default, "" for none
+    pub pager: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            library: None,
+            viewer: None,
+            download_dir: None,
+            concurrency: 5,
+            http: HttpConfig::default(),
+            citekey_template: None,
+            edit_mode: EditMode::Emacs,
+            columns: None,
+            pager: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn citekey_template(&self) -> &str {
+        self.citekey_template
+            .as_deref()
+            .unwrap_or(lint::DEFAULT_TEMPLATE)
+    }
+
+    fn from_file(path: &Path) -> Result<Config, Box<dyn Error>> {
+        let mut config: Config = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        config.library = config.library.map(expand_home);
+        config.download_dir = config.download_dir.map(expand_home);
+        config.concurrency = config.concurrency.max(1);
+        Ok(config)
+    }
+}
+
+pub fn path() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("refhelper").join("config.json"))
+}
+
+// $XDG_STATE_HOME/refhelper (~/.local/state/refhelper) keeps files like shell history
+pub fn state_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_STATE_HOME") {
+        Some(d) if !d.is_empty() => PathBuf::from(d),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".local")
+            .join("state"),
+    };
+    Some(dir.join("refhelper"))
+}
+
+// read config file once at startup, defaults if there is none
+pub fn load() -> Result<&'static Config, Box<dyn Error>> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+    let config = match path() {
+        Some(p) if p.exists() => Config::from_file(&p)
+            .map_err(|e| format!("invalid config file {}: {}", p.display(), e))?,
+        _ => Config::default(),
+    };
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+// loaded config, defaults if `load` is not called, e.g. in tests
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+// "~/papers" -> "$HOME/papers"
+fn expand_home(path: PathBuf) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_config() -> Result<(), Box<dyn Error>> {
+        let path = env::temp_dir().join(format!("refhelper-config-{}.json", std::process::id()));
+        let json = r#"{"library": "~/refs.json", "edit_mode": "vi", "http": {"timeout": 30},
+            "columns": ["id", "author", "year"]}"#;
+        std::fs::write(&path, json)?;
+        let config = Config::from_file(&path)?;
+        std::fs::remove_file(path)?;
+        assert!(config.library.as_ref().unwrap().ends_with("refs.json"));
+        assert_eq!(config.edit_mode, EditMode::Vi);
+        assert_eq!(config.http.timeout, Some(30));
+        assert_eq!(config.http.user_agent, "hyper/0.5.2");
+        assert_eq!(config.concurrency, 5);
+        assert_eq!(config.columns.as_ref().unwrap()[1], Column::Author);
+        assert_eq!(config.citekey_template(), lint::DEFAULT_TEMPLATE);
+        Ok(())
+    }
+}
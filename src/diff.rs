@@ -0,0 +1,187 @@
+// compare two libraries and find the same paper in both,
+// entries are the same if they share uid, DOI, arXiv id or title
+use biblatex::ChunksExt;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::index::tokenize;
+use crate::normalize::clean_doi;
+use crate::Entry;
+
+pub enum Change {
+    Added(usize),
+    Removed(usize),
+    Changed(usize, usize, Vec<FieldChange>),
+}
+
+pub struct FieldChange {
+    pub field: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+// identifiers of an entry, e.g. "doi:10.1145/2486001.2486012", "arxiv:1904.12901"
+fn identifiers(entry: &Entry) -> Vec<String> {
+    let mut ids = Vec::new();
+    if !entry.uid.is_empty() {
+        ids.push(format!("uid:{}", entry.uid));
+    }
+    let mut add_id = |id: &str| {
+        let id = clean_doi(id).to_lowercase();
+        if id.starts_with("10.") {
+            ids.push(format!("doi:{}", id));
+        } else if !id.is_empty() {
+            // arXiv versions are the same paper
+            let id = id.trim_start_matches("arxiv:");
+            let id = match id.rfind('v') {
+                Some(i) if i > 0 && id[i + 1..].chars().all(|c| c.is_ascii_digit()) => &id[..i],
+                _ => id,
+            };
+            ids.push(format!("arxiv:{}", id));
+        }
+    };
+    add_id(&entry.doi);
+    if let Some(b) = entry.parsed() {
+        for field in ["doi", "eprint"].iter() {
+            if let Some(id) = b.get(field) {
+                add_id(&id.format_verbatim());
+            }
+        }
+    }
+    let title = tokenize(&entry.title).join(" ");
+    // short titles like "Introduction" are not distinctive
+    if title.len() > 20 {
+        ids.push(format!("title:{}", title));
+    }
+    ids.sort();
+    ids.dedup();
+    ids
+}
+
+// index of entries by their identifiers
+pub struct Matcher {
+    ids: HashMap<String, usize>,
+}
+
+impl Matcher {
+    pub fn new(entries: &[Entry]) -> Matcher {
+        let mut ids = HashMap::new();
+        for (i, e) in entries.iter().enumerate() {
+            for id in identifiers(e) {
+                ids.entry(id).or_insert(i);
+            }
+        }
+        Matcher { ids }
+    }
+
+    pub fn find(&self, entry: &Entry) -> Option<usize> {
+        identifiers(entry)
+            .iter()
+            .find_map(|id| self.ids.get(id).copied())
+    }
+
+    pub fn insert(&mut self, entry: &Entry, i: usize) {
+        for id in identifiers(entry) {
+            self.ids.entry(id).or_insert(i);
+        }
+    }
+}
+
+// fields of parsed bibtex, with citekey, entry type and note
+fn fields(entry: &Entry) -> BTreeMap<String, String> {
+    let mut fields = BTreeMap::new();
+    if let Some(b) = entry.parsed() {
+        fields.insert(String::from("type"), b.entry_type.to_string());
+        for (k, v) in b.fields.iter() {
+            fields.insert(k.clone(), v.format_verbatim());
+        }
+    }
+    fields.insert(String::from("citekey"), entry.name.clone());
+    if !entry.note.is_empty() {
+        fields.insert(String::from("note"), entry.note.clone());
+    }
+    fields
+}
+
+pub fn diff(old: &[Entry], new: &[Entry]) -> Vec<Change> {
+    let matcher = Matcher::new(new);
+    let mut matched = vec![false; new.len()];
+    let mut changes = Vec::new();
+    for (i, e) in old.iter().enumerate() {
+        let j = match matcher.find(e) {
+            Some(j) if !matched[j] => j,
+            _ => {
+                changes.push(Change::Removed(i));
+                continue;
+            }
+        };
+        matched[j] = true;
+        let (a, b) = (fields(e), fields(&new[j]));
+        let keys: Vec<&String> = a
+            .keys()
+            .chain(b.keys().filter(|k| !a.contains_key(*k)))
+            .collect();
+        let field_changes: Vec<FieldChange> = keys
+            .into_iter()
+            .filter(|k| a.get(*k) != b.get(*k))
+            .map(|k| FieldChange {
+                field: k.clone(),
+                old: a.get(k).cloned(),
+                new: b.get(k).cloned(),
+            })
+            .collect();
+        if !field_changes.is_empty() {
+            changes.push(Change::Changed(i, j, field_changes));
+        }
+    }
+    for (j, m) in matched.iter().enumerate() {
+        if !m {
+            changes.push(Change::Added(j));
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    fn entry(name: &str, doi: &str, bibtex: &str) -> Entry {
+        let mut e = Entry::new(name, doi);
+        e.bibtex = String::from(bibtex);
+        e
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = vec![
+            entry(
+                "swan",
+                "10.1145/2486001.2486012",
+                "@article{swan, year = {2013}}",
+            ),
+            entry("rl", "1904.12901", "@article{rl, year = {2019}}"),
+        ];
+        let new = vec![
+            entry("other", "10.1000/1", "@article{other, year = {2020}}"),
+            entry(
+                "swan2",
+                "https://doi.org/10.1145/2486001.2486012",
+                "@article{swan2, year = {2014}}",
+            ),
+        ];
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 3);
+        match &changes[0] {
+            Change::Changed(0, 1, fields) => {
+                let names: Vec<_> = fields.iter().map(|f| f.field.as_str()).collect();
+                assert_eq!(names, vec!["citekey", "year"]);
+            }
+            _ => panic!("expect a changed entry"),
+        }
+        assert!(matches!(changes[1], Change::Removed(1)));
+        assert!(matches!(changes[2], Change::Added(0)));
+        // arXiv versions match
+        let v2 = entry("rl", "1904.12901v2", "");
+        assert_eq!(Matcher::new(&old).find(&v2), Some(1));
+    }
+}
@@ -1,7 +1,19 @@
 use futures::{stream, StreamExt};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use scraper::{Html, Selector};
-use std::{error::Error, fs::File, io::Write, path::PathBuf};
+use std::{
+    error::Error,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::config;
+use crate::normalize::clean_doi;
+
+// seconds to wait for doi.org when no timeout is configured
+const RESOLVE_TIMEOUT: u64 = 10;
 
 fn set_pb_style(pb: &ProgressBar) {
     pb.set_style(
@@ -17,44 +29,107 @@ fn set_pb_err(pb: &ProgressBar, msg: String) {
     pb.set_message(msg);
 }
 
+// client with http settings of config
+pub fn client() -> reqwest::Result<reqwest::Client> {
+    let http = &config::get().http;
+    let mut builder = reqwest::Client::builder().user_agent(http.user_agent.clone());
+    if let Some(secs) = http.timeout {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    if let Some(proxy) = &http.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    builder.build()
+}
+
 pub struct Downloader;
 
 impl Downloader {
     pub async fn get_pdf(id: &str, path: PathBuf, pb: &ProgressBar) -> Result<(), Box<dyn Error>> {
         if id.starts_with("10.") || id.is_empty() {
-            set_pb_err(pb, format!("download '{}' error: Not implemented", id));
-        } else {
-            if let Err(e) = ArxivDownloader::get_pdf(id, path, pb).await {
-                set_pb_err(pb, format!("download '{}' error: {}", id, e));
-            }
+            return Err("Not implemented".into());
         }
-        Ok(())
+        ArxivDownloader::get_pdf(id, path, pb).await
+    }
+}
+
+// identifiers in `citation_*` meta tags of a paper page, as read by Google Scholar
+#[derive(Debug, Default)]
+pub struct CitationMeta {
+    pub doi: Option<String>,
+    pub arxiv_id: Option<String>,
+    pub pdf_url: Option<String>,
+}
+
+pub fn citation_meta(html: &str) -> CitationMeta {
+    let page = Html::parse_document(html);
+    let meta = |name: &str| {
+        let selector = Selector::parse(&format!(r#"[name="{}"]"#, name)).unwrap();
+        page.select(&selector)
+            .find_map(|ele| ele.value().attr("content"))
+            .map(|s| s.trim().to_owned())
+            .filter(|s| !s.is_empty())
+    };
+    CitationMeta {
+        doi: meta("citation_doi").map(|d| clean_doi(&d)),
+        arxiv_id: meta("citation_arxiv_id"),
+        pdf_url: meta("citation_pdf_url"),
+    }
+}
+
+// download a file, removed again if the download fails
+pub async fn download_file(url: &str, path: &Path, pb: &ProgressBar) -> Result<(), Box<dyn Error>> {
+    let client = client()?;
+    let res = client.get(url).send().await?.error_for_status()?;
+
+    let total_size = res.content_length().ok_or("Failed to get file length")?;
+    pb.set_length(total_size);
+
+    let mut file = File::create(path)?;
+    let mut downloaded: usize = 0;
+    let mut stream = res.bytes_stream();
+    while let Some(item) = stream.next().await {
+        let chunk = match item {
+            Ok(c) => c,
+            Err(e) => {
+                drop(file);
+                std::fs::remove_file(path)?;
+                return Err(e.into());
+            }
+        };
+        file.write_all(&chunk)?;
+        downloaded += chunk.len();
+        pb.set_position(downloaded as u64);
+    }
+    // do not leave a truncated pdf file to be linked
+    if downloaded as u64 != total_size {
+        drop(file);
+        std::fs::remove_file(path)?;
+        return Err(format!(
+            "truncated download, got {}/{} bytes",
+            downloaded, total_size
+        )
+        .into());
     }
+    pb.finish();
+    Ok(())
 }
+
 pub struct ArxivDownloader;
 
 impl ArxivDownloader {
-    async fn arxiv2doi(aid: &str) -> Result<Option<String>, Box<dyn Error>> {
+    pub async fn arxiv2doi(aid: &str) -> Result<Option<String>, Box<dyn Error>> {
         let url = format!("https://arxiv.org/abs/{}", aid);
-        let client = reqwest::Client::builder()
-            .user_agent("hyper/0.5.2".to_owned())
-            .build()?;
+        let client = client()?;
         let body = client.get(url).send().await?.text().await?;
-        let selector = Selector::parse(r#"[name="citation_doi"]"#).unwrap();
-        let doi = Html::parse_fragment(&body)
-            .select(&selector)
-            .next()
-            .and_then(|ele| ele.value().attr("content").map(|s| s.into()));
-        Ok(doi)
+        Ok(citation_meta(&body).doi)
     }
 
     pub async fn get_bibtex(id: &str) -> Result<String, Box<dyn Error>> {
         let doi: Option<String> = Self::arxiv2doi(id).await?;
         if doi.is_none() {
             let url = format!("https://arxiv.org/bibtex/{}", id);
-            let client = reqwest::Client::builder()
-                .user_agent("hyper/0.5.2".to_owned())
-                .build()?;
+            let client = client()?;
             let body = client
                 .get(url)
                 .header("Accept", "text/bibliography; style=bibtex")
@@ -70,31 +145,12 @@ impl ArxivDownloader {
     }
 
     pub async fn get_pdf(id: &str, path: PathBuf, pb: &ProgressBar) -> Result<(), Box<dyn Error>> {
-        let url = format!("https://arxiv.org/pdf/{}", id);
-        let client = reqwest::Client::builder()
-            .user_agent("hyper/0.5.2".to_owned())
-            .build()?;
-        let res = client
-            .get(url)
-            .header("Accept", "text/bibliography; style=bibtex")
-            .send()
-            .await?;
-
-        let total_size = res.content_length().ok_or("Failed to get file length")?;
-        pb.set_length(total_size);
         pb.set_message(format!("{}.pdf", id));
+        download_file(&Self::pdf_url(id), &path, pb).await
+    }
 
-        let mut file = File::create(path)?;
-        let mut downloaded: usize = 0;
-        let mut stream = res.bytes_stream();
-        while let Some(item) = stream.next().await {
-            let chunk = item?;
-            let len = file.write(&chunk)?;
-            downloaded += len;
-            pb.set_position(downloaded as u64);
-        }
-        pb.finish();
-        Ok(())
+    pub fn pdf_url(id: &str) -> String {
+        format!("https://arxiv.org/pdf/{}", id)
     }
 }
 pub struct DOIDownloader;
@@ -102,7 +158,7 @@ pub struct DOIDownloader;
 impl DOIDownloader {
     pub async fn get_bibtex(doi: &str) -> Result<String, Box<dyn Error>> {
         let url = format!("https://doi.org/{}", doi);
-        let client = reqwest::Client::new();
+        let client = client()?;
         let body = client
             .get(url)
             .header("Accept", "application/x-bibtex; charset=utf-8")
@@ -112,10 +168,19 @@ impl DOIDownloader {
             .await?;
         Ok(body)
     }
+
+    // handle api of doi.org answers 404 for unknown DOIs
+    pub async fn resolves(doi: &str) -> Result<bool, Box<dyn Error>> {
+        let url = format!("https://doi.org/api/handles/{}", doi);
+        // many DOIs are checked at once, a slow answer does not block the others
+        let timeout = config::get().http.timeout.unwrap_or(RESOLVE_TIMEOUT);
+        let req = client()?.get(url).timeout(Duration::from_secs(timeout));
+        let res = req.send().await?;
+        Ok(res.status().is_success())
+    }
 }
 
 pub async fn download_pdfs(ids: Vec<&str>, path: PathBuf) -> Result<(), Box<dyn Error>> {
-    const CONCURRENT_NUM: usize = 5;
     let m = MultiProgress::new();
     let total_pb = m.add(ProgressBar::new(ids.len() as u64));
     total_pb.set_message("downloading");
@@ -127,28 +192,38 @@ pub async fn download_pdfs(ids: Vec<&str>, path: PathBuf) -> Result<(), Box<dyn
     let pbs: Vec<_> = (0..ids.len())
         .map(|i| m.insert(i, ProgressBar::new(0)))
         .collect();
-    let tasks = ids.iter().enumerate().map(|(i, id)| {
-        let mut p = path.clone();
-        p.push(format!("{}.pdf", id));
-        set_pb_style(&pbs[i]);
-        Downloader::get_pdf(id, p, &pbs[i])
-    });
+    pbs.iter().for_each(set_pb_style);
 
     let handle_m = tokio::task::spawn_blocking(move || m.join().unwrap());
-    stream::iter(tasks)
-        .map(|task| async {
-            let r = task.await;
-            total_pb.inc(1);
-            r
-        })
-        .buffered(CONCURRENT_NUM)
-        .collect::<Vec<_>>()
-        .await;
+    download_with(&ids, &path, &pbs, &total_pb).await;
     total_pb.finish_with_message("done");
     handle_m.await?;
     Ok(())
 }
 
+// download pdf files named by their ids into path, progress of i-th file goes to pbs[i]
+// and `total` counts finished files, hidden bars can be read by other views
+pub async fn download_with(
+    ids: &[&str],
+    path: &Path,
+    pbs: &[ProgressBar],
+    total: &ProgressBar,
+) -> Vec<Result<(), String>> {
+    let tasks = ids.iter().zip(pbs).map(|(id, pb)| async move {
+        let file = path.join(format!("{}.pdf", id));
+        let r = Downloader::get_pdf(id, file, pb).await;
+        if let Err(e) = &r {
+            set_pb_err(pb, format!("download '{}' error: {}", id, e));
+        }
+        total.inc(1);
+        r.map_err(|e| e.to_string())
+    });
+    stream::iter(tasks)
+        .buffered(config::get().concurrency)
+        .collect::<Vec<_>>()
+        .await
+}
+
 #[cfg(test)]
 mod tests {
 
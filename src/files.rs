@@ -0,0 +1,146 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// integrity metadata of a linked file, recorded when it is linked
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileMeta {
+    pub sha256: String,
+    pub size: u64,
+    pub linked_at: u64, // seconds since unix epoch
+}
+
+impl FileMeta {
+    pub fn from_path(path: &Path) -> io::Result<FileMeta> {
+        let linked_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        Ok(FileMeta {
+            sha256: sha256_file(path)?,
+            size: std::fs::metadata(path)?.len(),
+            linked_at,
+        })
+    }
+
+    // check whether file at path still has the recorded content
+    pub fn matches(&self, path: &Path) -> bool {
+        match std::fs::metadata(path) {
+            Ok(m) if m.len() == self.size => sha256_file(path).is_ok_and(|h| h == self.sha256),
+            _ => false,
+        }
+    }
+}
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// state of a linked file on disk
+#[derive(Debug, PartialEq)]
+pub enum FileStatus {
+    Ok,
+    Missing,
+    Unreadable(String),
+    NotPdf,
+}
+
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FileStatus::Ok => write!(f, "ok"),
+            FileStatus::Missing => write!(f, "missing"),
+            FileStatus::Unreadable(e) => write!(f, "unreadable ({})", e),
+            FileStatus::NotPdf => write!(f, "not a pdf file"),
+        }
+    }
+}
+
+pub fn check_file(path: &Path) -> FileStatus {
+    if !path.exists() {
+        return FileStatus::Missing;
+    }
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => return FileStatus::Unreadable(e.to_string()),
+    };
+    // every pdf file starts with "%PDF-"
+    let mut magic = [0u8; 5];
+    match file.read_exact(&mut magic) {
+        Ok(()) if &magic == b"%PDF-" => FileStatus::Ok,
+        Ok(()) => FileStatus::NotPdf,
+        Err(_) => FileStatus::NotPdf,
+    }
+}
+
+pub fn is_pdf_name(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"))
+}
+
+// collect all pdf files under folder recursively
+pub fn find_pdfs(folder: &Path) -> Vec<PathBuf> {
+    let mut pdfs = Vec::new();
+    let mut dirs = vec![folder.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let read_dir = match std::fs::read_dir(&dir) {
+            Ok(r) => r,
+            Err(_) => continue,
+        };
+        for item in read_dir.flatten() {
+            let path = item.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if is_pdf_name(&path) {
+                pdfs.push(path);
+            }
+        }
+    }
+    pdfs.sort();
+    pdfs
+}
+
+// absolute path following symlinks, or the path itself if it does not exist
+pub fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+// compare two paths, following symlinks and relative paths if possible
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_check_file() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join("refhelper_test_check_file");
+        std::fs::create_dir_all(&dir)?;
+        let pdf = dir.join("a.pdf");
+        let txt = dir.join("b.pdf");
+        File::create(&pdf)?.write_all(b"%PDF-1.5\n")?;
+        File::create(&txt)?.write_all(b"<html></html>")?;
+        assert_eq!(check_file(&pdf), FileStatus::Ok);
+        assert_eq!(check_file(&txt), FileStatus::NotPdf);
+        assert_eq!(check_file(&dir.join("c.pdf")), FileStatus::Missing);
+        assert_eq!(find_pdfs(&dir), vec![pdf.clone(), txt.clone()]);
+        let meta = FileMeta::from_path(&pdf)?;
+        assert_eq!(meta.size, 9);
+        assert!(meta.matches(&pdf));
+        assert!(!meta.matches(&txt));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+
+const SNIPPET_CONTEXT: usize = 40;
+
+// text of linked pdf files, keyed by their sha256 so that
+// the same file is extracted only once even if moved or linked twice
+#[derive(Serialize, Deserialize, Default)]
+pub struct FullTextIndex {
+    docs: HashMap<String, Vec<String>>, // text of each page
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+pub struct TextMatch {
+    pub page: usize, // starts from 1
+    pub snippet: String,
+}
+
+impl FullTextIndex {
+    // index is stored next to library, e.g. refs.json -> refs.fulltext.json
+    pub fn open(lib_path: &Path) -> FullTextIndex {
+        let path = lib_path.with_extension("fulltext.json");
+        let mut index: FullTextIndex = File::open(&path)
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        index.path = Some(path);
+        index
+    }
+
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.docs.contains_key(sha256)
+    }
+
+    pub fn insert(&mut self, sha256: &str, file: &Path) -> Result<(), Box<dyn Error>> {
+        let pages = extract_pages(file)?;
+        self.docs.insert(String::from(sha256), pages);
+        self.dirty = true;
+        Ok(())
+    }
+
+    // drop text of files which are no longer linked
+    pub fn retain(&mut self, linked: &HashSet<&str>) {
+        let len = self.docs.len();
+        self.docs.retain(|k, _| linked.contains(k.as_str()));
+        self.dirty |= len != self.docs.len();
+    }
+
+    pub fn search(&self, sha256: &str, pat: &str) -> Vec<TextMatch> {
+        let pat = pat.to_lowercase();
+        let pages = match self.docs.get(sha256) {
+            Some(p) if !pat.is_empty() => p,
+            _ => return vec![],
+        };
+        let mut matches = Vec::new();
+        for (i, text) in pages.iter().enumerate() {
+            let (lower, chars) = lowercase(text);
+            for (pos, _) in lower.match_indices(&pat) {
+                let (start, end) = (chars[pos].0, chars[pos + pat.len() - 1].1);
+                matches.push(TextMatch {
+                    page: i + 1,
+                    snippet: snippet(text, start, end - start),
+                });
+            }
+        }
+        matches
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if let (true, Some(p)) = (self.dirty, &self.path) {
+            serde_json::to_writer(BufWriter::new(File::create(p)?), self)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+fn extract_pages(file: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    // pdf parser may panic on malformed files
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_by_pages(file))
+        .map_err(|_| "failed to parse pdf file")??;
+    Ok(pages
+        .iter()
+        .map(|p| p.split_whitespace().collect::<Vec<_>>().join(" "))
+        .collect())
+}
+
+// lowercase may change byte length of some characters, e.g. 'İ',
+// so each byte of the lowercase text is mapped to the range of its character in text
+fn lowercase(text: &str) -> (String, Vec<(usize, usize)>) {
+    let mut lower = String::with_capacity(text.len());
+    let mut chars = Vec::with_capacity(text.len());
+    for (start, c) in text.char_indices() {
+        lower.extend(c.to_lowercase());
+        chars.resize(lower.len(), (start, start + c.len_utf8()));
+    }
+    (lower, chars)
+}
+
+fn snippet(text: &str, pos: usize, len: usize) -> String {
+    let mut start = pos.saturating_sub(SNIPPET_CONTEXT);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (pos + len + SNIPPET_CONTEXT).min(text.len());
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        &text[start..end],
+        if end < text.len() { "..." } else { "" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_search() {
+        let mut index = FullTextIndex::default();
+        index.docs.insert(
+            String::from("hash"),
+            vec![
+                String::from("Abstract. We study Software Defined Networking."),
+                String::from("software defined networks are everywhere"),
+            ],
+        );
+        let matches = index.search("hash", "software defined");
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].page, 1);
+        assert_eq!(
+            matches[0].snippet,
+            "Abstract. We study Software Defined Networking."
+        );
+        assert_eq!(matches[1].page, 2);
+        assert!(index.search("other", "software").is_empty());
+
+        // 'ẞ' is one byte shorter in lowercase, and 'İ' one byte longer
+        let text = format!("ẞ{} Match İ{}", "x".repeat(60), "y".repeat(60));
+        index.docs.insert(String::from("case"), vec![text]);
+        let matches = index.search("case", "match i\u{307}");
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            matches[0].snippet,
+            format!("...{} Match İ{}...", "x".repeat(39), "y".repeat(40))
+        );
+    }
+}
@@ -0,0 +1,240 @@
+use biblatex::ChunksExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::Entry;
+
+// indexed fields and their weights in ranking
+const FIELDS: &[(&str, f64)] = &[
+    ("name", 3.0),
+    ("title", 3.0),
+    ("author", 2.0),
+    ("year", 1.0),
+    ("venue", 1.0),
+    ("id", 1.0),
+    ("keywords", 1.0),
+    ("note", 0.5),
+];
+
+// inverted index over parsed fields of entries,
+// entries are keyed by a fingerprint of their content so that
+// only added or changed entries have to be tokenized again
+#[derive(Serialize, Deserialize, Default)]
+pub struct SearchIndex {
+    docs: HashMap<String, u32>,                 // fingerprint -> doc number
+    postings: BTreeMap<String, Vec<(u32, u8)>>, // term -> (doc number, field)
+    next_doc: u32,
+
+    #[serde(skip)]
+    path: Option<PathBuf>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+impl SearchIndex {
+    // index is stored next to library, e.g. refs.json -> refs.index.json
+    pub fn open(lib_path: &Path) -> SearchIndex {
+        let path = lib_path.with_extension("index.json");
+        let mut index: SearchIndex = File::open(&path)
+            .ok()
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)).ok())
+            .unwrap_or_default();
+        index.path = Some(path);
+        index
+    }
+
+    // index new or changed entries and remove stale ones,
+    // returns fingerprint of every entry
+    pub fn sync(&mut self, entries: &[Entry]) -> Vec<String> {
+        let fps: Vec<String> = entries.iter().map(fingerprint).collect();
+        let current: HashSet<&str> = fps.iter().map(|s| s.as_str()).collect();
+        let stale: HashSet<u32> = self
+            .docs
+            .iter()
+            .filter(|(k, _)| !current.contains(k.as_str()))
+            .map(|(_, &doc)| doc)
+            .collect();
+        if !stale.is_empty() {
+            self.docs.retain(|_, doc| !stale.contains(doc));
+            self.postings.retain(|_, list| {
+                list.retain(|(doc, _)| !stale.contains(doc));
+                !list.is_empty()
+            });
+            self.dirty = true;
+        }
+        for (entry, fp) in entries.iter().zip(fps.iter()) {
+            if self.docs.contains_key(fp) {
+                continue;
+            }
+            let doc = self.next_doc;
+            self.next_doc += 1;
+            self.docs.insert(fp.clone(), doc);
+            // terms are unique per entry, no need to check duplicates
+            for (field, term) in entry_terms(entry) {
+                self.postings.entry(term).or_default().push((doc, field));
+            }
+            self.dirty = true;
+        }
+        fps
+    }
+
+    // every query word has to match a term exactly or as prefix,
+    // a word like "author:smith" only matches in that field
+    pub fn query(&self, query: &str) -> Vec<(String, f64)> {
+        let mut scores: HashMap<u32, f64> = HashMap::new();
+        let words: Vec<(Option<u8>, String)> = query
+            .split_whitespace()
+            .flat_map(|word| {
+                let (field, text) = match word.split_once(':') {
+                    Some((f, t)) => match field_id(f) {
+                        Some(id) => (Some(id), t),
+                        None => (None, word),
+                    },
+                    None => (None, word),
+                };
+                tokenize(text).into_iter().map(move |t| (field, t))
+            })
+            .collect();
+        for (i, (field, word)) in words.iter().enumerate() {
+            let mut word_scores: HashMap<u32, f64> = HashMap::new();
+            for (term, list) in self
+                .postings
+                .range(word.clone()..)
+                .take_while(|(t, _)| t.starts_with(word.as_str()))
+            {
+                let exact = if term == word { 1.0 } else { 0.5 };
+                let idf = (1.0 + self.docs.len() as f64 / list.len() as f64).ln();
+                for (doc, f) in list.iter().filter(|(_, f)| field.is_none_or(|x| x == *f)) {
+                    let score = FIELDS[*f as usize].1 * exact * idf;
+                    let s = word_scores.entry(*doc).or_insert(0.0);
+                    *s = s.max(score);
+                }
+            }
+            if i == 0 {
+                scores = word_scores;
+            } else {
+                scores.retain(|doc, _| word_scores.contains_key(doc));
+                for (doc, s) in scores.iter_mut() {
+                    *s += word_scores[doc];
+                }
+            }
+        }
+        let fps: HashMap<u32, &String> = self.docs.iter().map(|(fp, &doc)| (doc, fp)).collect();
+        let mut result: Vec<(String, f64)> = scores
+            .into_iter()
+            .map(|(doc, s)| (fps[&doc].clone(), s))
+            .collect();
+        // equal scores in fingerprint order, so that results do not depend on hashing
+        result.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        result
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if let (true, Some(p)) = (self.dirty, &self.path) {
+            serde_json::to_writer(BufWriter::new(File::create(p)?), self)?;
+            self.dirty = false;
+        }
+        Ok(())
+    }
+}
+
+fn field_id(name: &str) -> Option<u8> {
+    // tags of entries are keywords of their bibtex
+    let name = match name {
+        "tag" | "tags" => "keywords",
+        n => n,
+    };
+    FIELDS.iter().position(|(f, _)| *f == name).map(|i| i as u8)
+}
+
+fn fingerprint(entry: &Entry) -> String {
+    let mut hasher = Sha256::new();
+    for s in [&entry.name, &entry.doi, &entry.bibtex, &entry.note] {
+        hasher.update(s.as_bytes());
+        hasher.update([0u8]);
+    }
+    hex::encode(hasher.finalize())
+}
+
+// case fold and strip diacritics, so that "Müller" matches "muller"
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.nfd()
+        .filter(|c| !unicode_normalization::char::is_combining_mark(*c))
+        .flat_map(|c| c.to_lowercase())
+        .collect::<String>()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn entry_terms(entry: &Entry) -> Vec<(u8, String)> {
+    let mut fields: Vec<(&str, String)> = vec![
+        ("name", entry.name.clone()),
+        ("id", entry.doi.clone()),
+        ("title", entry.title.clone()),
+        ("note", entry.note.clone()),
+    ];
+    if let Some(e) = entry.parsed() {
+        for person in e.author().unwrap_or_default() {
+            // surname with its prefix and given names, "von Neumann, J." -> "von neumann j"
+            fields.push(("author", format!("{} {}", person.prefix, person.name)));
+            fields.push(("author", person.given_name.clone()));
+        }
+        if let Some(date) = e.date() {
+            if let biblatex::DateValue::At(d) = date.value {
+                fields.push(("year", d.year.to_string()));
+            }
+        }
+        for venue in [e.journal(), e.book_title()].iter().flatten() {
+            fields.push(("venue", venue.format_verbatim()));
+        }
+        if let Some(k) = e.keywords() {
+            fields.push(("keywords", k.format_verbatim()));
+        }
+    }
+    let mut terms: Vec<(u8, String)> = fields
+        .into_iter()
+        .flat_map(|(f, text)| {
+            let id = field_id(f).unwrap();
+            tokenize(&text).into_iter().map(move |t| (id, t))
+        })
+        .collect();
+    terms.sort();
+    terms.dedup();
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_query() {
+        let mut e1 = Entry::new("swan", "10.1145/2486001.2486012");
+        e1.bibtex = String::from(
+            "@inproceedings{swan, title = {Achieving high utilization with software-driven WAN}, \
+             author = {Hong, Chi-Yao and M{\\\"u}ller, Hans}, year = {2013}, booktitle = {SIGCOMM}}",
+        );
+        e1.title = String::from("Achieving high utilization with software-driven WAN");
+        let e2 = Entry::new("other", "1904.12901");
+        let mut index = SearchIndex::default();
+        let fps = index.sync(&[e1, e2]);
+        let result = index.query("soft wan");
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].0, fps[0]);
+        assert_eq!(index.query("author:muller year:2013").len(), 1);
+        assert!(index.query("title:sigcomm").is_empty());
+        assert_eq!(index.query("sigcomm")[0].0, fps[0]);
+        // stale entries are removed
+        index.sync(&[]);
+        assert!(index.query("wan").is_empty());
+        assert!(index.postings.is_empty());
+    }
+}
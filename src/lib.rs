@@ -1,42 +1,103 @@
 use biblatex::{self, Bibliography, ChunksExt};
 use futures::{stream, StreamExt};
 use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
 use indicatif::ProgressBar;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::runtime::Runtime;
 
+use attachment::{Attachment, AttachmentKind};
+use record::{ListOptions, Output};
+
+mod abbrev;
+mod attachment;
+mod bundle;
 pub mod cli;
+pub mod config;
+mod diff;
 pub mod downloader;
+mod files;
+mod fulltext;
+mod index;
+pub mod lint;
+pub mod lsp;
+mod merge;
+pub mod normalize;
+mod record;
+mod render;
 mod rustyline;
+mod schema;
+pub mod server;
+mod storage;
+pub mod tui;
 mod utils;
+mod zotero;
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
 pub struct Entry {
+    #[serde(default)]
+    uid: String,
     name: String,
     doi: String,
+    #[serde(default)]
     bibtex: String,
+    #[serde(default)]
     title: String,
-    path: Option<PathBuf>,
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    #[serde(default)]
     note: String,
+
+    // fields of newer versions, kept as is
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
 }
 
 impl Entry {
     pub fn new(name: &str, doi: &str) -> Entry {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
         Entry {
+            uid: schema::uid(&[name, doi, &now]),
             name: String::from(name),
             doi: String::from(doi), // if arxiv paper, it is arxiv id
             bibtex: String::from(""),
-            path: None,
+            attachments: vec![],
             title: String::from(""),
             note: String::from(""),
+            extra: serde_json::Map::new(),
         }
     }
 
+    // link the main pdf file, replacing the old one if exists
     pub fn link(&mut self, path: PathBuf) {
-        self.path = Some(path);
+        match self
+            .attachments
+            .iter_mut()
+            .find(|a| a.kind == AttachmentKind::Pdf)
+        {
+            Some(a) => a.relink(path),
+            None => self
+                .attachments
+                .insert(0, Attachment::from_file(AttachmentKind::Pdf, path)),
+        }
+    }
+
+    pub fn attach(&mut self, attachment: Attachment) {
+        self.attachments.push(attachment);
+    }
+
+    pub fn pdf(&self) -> Option<&Path> {
+        self.attachments
+            .iter()
+            .find(|a| a.kind == AttachmentKind::Pdf)
+            .and_then(|a| a.path())
     }
 
     pub async fn get_bib(&mut self) -> Result<(), Box<dyn Error>> {
@@ -50,6 +111,11 @@ impl Entry {
         Ok(())
     }
 
+    // bibtex parsed by biblatex, None if it is empty or invalid
+    fn parsed(&self) -> Option<biblatex::Entry> {
+        Bibliography::parse(&self.bibtex)?.into_iter().next()
+    }
+
     fn parse_bibtex(&mut self, bib: String) -> Result<(), String> {
         let mut bibs = Bibliography::parse(&bib).unwrap();
         // only one entry
@@ -68,6 +134,39 @@ impl Entry {
         }
     }
 
+    // change citekey, in bibtex as well
+    pub fn rename(&mut self, name: &str) {
+        self.name = String::from(name);
+        if let Some(mut e) = self.parsed() {
+            e.key = self.name.clone();
+            self.bibtex = e.to_bibtex_string();
+        }
+    }
+
+    // tags are keywords of bibtex
+    pub fn tags(&self) -> Vec<String> {
+        self.parsed()
+            .and_then(|b| b.get("keywords").map(|k| k.format_verbatim()))
+            .map_or(vec![], |k| {
+                k.split(',')
+                    .map(|t| t.trim().to_owned())
+                    .filter(|t| !t.is_empty())
+                    .collect()
+            })
+    }
+
+    pub fn set_tags(&mut self, tags: &[String]) -> Result<(), String> {
+        let mut bib = self.parsed().ok_or("entry has no valid bibtex")?;
+        if tags.is_empty() {
+            bib.fields.remove("keywords");
+        } else {
+            let keywords = vec![biblatex::Chunk::Normal(tags.join(", "))];
+            bib.set("keywords", keywords);
+        }
+        self.bibtex = bib.to_bibtex_string();
+        Ok(())
+    }
+
     pub fn take_note(&mut self, note: &str) {
         self.note = String::from(note);
     }
@@ -75,46 +174,223 @@ impl Entry {
     pub fn print(&self) {
         println!("name: {} title: {}", self.name, self.title);
     }
+
+    // lines of all fields, tags, attachments and the note, for `show` and the TUI
+    pub fn details(&self) -> Vec<String> {
+        let mut lines = vec![format!("name: {}", self.name), format!("doi: {}", self.doi)];
+        if let Some(bib) = self.parsed() {
+            lines.push(format!("type: {}", bib.entry_type.to_bibtex()));
+            let mut fields: Vec<_> = bib
+                .fields
+                .iter()
+                .filter(|(k, _)| *k != "keywords")
+                .map(|(k, v)| (k.clone(), v.format_verbatim()))
+                .collect();
+            normalize::sort_fields(&mut fields);
+            lines.extend(fields.iter().map(|(k, v)| format!("{}: {}", k, v)));
+        }
+        let tags = self.tags();
+        if !tags.is_empty() {
+            lines.push(format!("tags: {}", tags.join(", ")));
+        }
+        if !self.attachments.is_empty() {
+            lines.push(String::new());
+            lines.push(String::from("attachments:"));
+            let attachments = self.attachments.iter().enumerate();
+            lines.extend(attachments.map(|(n, a)| format!("  {} {}", n, a)));
+        }
+        if !self.note.is_empty() {
+            lines.push(String::new());
+            lines.push(String::from("note:"));
+            lines.extend(self.note.lines().map(String::from));
+        }
+        lines
+    }
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Default)]
 pub struct Library {
     entries: Vec<Entry>,
-
-    #[serde(skip)]
     path: Option<PathBuf>,
+    storage: Option<Box<dyn storage::Storage>>,
+    // citekeys, DOIs or positions of entries may differ from storage since load or save
+    changed: bool,
+    // fingerprints of entries when the search index was synced, None once entries change
+    fingerprints: Option<Vec<String>>,
+    fulltext: fulltext::FullTextIndex,
+    index: index::SearchIndex,
 }
 
 impl Library {
     pub fn from_path(path: PathBuf) -> Result<Library, Box<dyn Error>> {
-        let lib = match File::open(&path) {
-            Ok(file) => {
-                let mut exist_lib: Library = serde_json::from_reader(file)?;
-                exist_lib.path = Some(path);
-                exist_lib
+        let mut storage = storage::open(&path)?;
+        let entries = storage.load()?;
+        Ok(Library {
+            entries,
+            fulltext: fulltext::FullTextIndex::open(&path),
+            index: index::SearchIndex::open(&path),
+            storage: Some(storage),
+            path: Some(path),
+            changed: false,
+            fingerprints: None,
+        })
+    }
+
+    // copy library to another file, e.g. from JSON to SQLite
+    pub fn migrate(from: &Path, to: &Path) -> Result<(), Box<dyn Error>> {
+        let n = storage::migrate(from, to)?;
+        println!("migrated {} entries to {}", n, to.display());
+        Ok(())
+    }
+
+    // three-way merge of JSON libraries, returns the number of conflicts,
+    // conflicting entries keep our values and record both sides in `conflicts`
+    pub fn merge(
+        base: &Path,
+        ours: &Path,
+        theirs: &Path,
+        out: &Path,
+    ) -> Result<usize, Box<dyn Error>> {
+        let conflicts = merge::merge_files(base, ours, theirs, out)?;
+        for c in conflicts.iter() {
+            println!("conflict in {}: {}", c.name, c.field);
+        }
+        Ok(conflicts.len())
+    }
+
+    // entries of another library file, which has to exist
+    fn load_entries(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+        if !path.exists() {
+            return Err(format!("{} does not exist", path.display()).into());
+        }
+        storage::read_entries(path)
+    }
+
+    // print entries added, removed and changed from library `a` to `b`
+    pub fn diff(a: &Path, b: &Path) -> Result<(), Box<dyn Error>> {
+        let (old, new) = (Library::load_entries(a)?, Library::load_entries(b)?);
+        let changes = diff::diff(&old, &new);
+        for change in changes.iter() {
+            match change {
+                diff::Change::Added(j) => {
+                    println!("+ [{}] {}: {}", j, new[*j].name, new[*j].title)
+                }
+                diff::Change::Removed(i) => {
+                    println!("- [{}] {}: {}", i, old[*i].name, old[*i].title)
+                }
+                diff::Change::Changed(i, j, fields) => {
+                    println!("~ [{} -> {}] {}: {}", i, j, new[*j].name, new[*j].title);
+                    for f in fields {
+                        let show = |v: &Option<String>| {
+                            v.as_deref().map_or(String::from("(none)"), utils::shorten)
+                        };
+                        println!("    {}: {} -> {}", f.field, show(&f.old), show(&f.new));
+                    }
+                }
             }
-            Err(_) => {
-                File::create(&path)?;
-                Library {
-                    path: Some(path),
-                    entries: vec![],
+        }
+        println!(
+            "{} changes from {} to {}",
+            changes.len(),
+            a.display(),
+            b.display()
+        );
+        Ok(())
+    }
+
+    // add entries of another library, duplicates found by DOI, arXiv id or title
+    // only bring their notes and attachments, colliding citekeys get a suffix
+    pub fn merge_from(&mut self, path: PathBuf) -> Result<(), String> {
+        let entries = Library::load_entries(&path).map_err(|e| format!("merge error: {}", e))?;
+        self.merge_entries(entries, &path);
+        Ok(())
+    }
+
+    fn merge_entries(&mut self, entries: Vec<Entry>, source: &Path) {
+        self.touch();
+        let mut matcher = diff::Matcher::new(&self.entries);
+        let mut used: HashSet<String> = self.entries.iter().map(|e| e.name.clone()).collect();
+        let (mut added, mut merged, mut renamed) = (0, 0, 0);
+        for mut entry in entries {
+            if let Some(i) = matcher.find(&entry) {
+                let target = &mut self.entries[i];
+                if target.note.is_empty() {
+                    target.note = entry.note;
+                } else if !entry.note.is_empty() && !target.note.contains(&entry.note) {
+                    target.note = format!("{}\n{}", target.note, entry.note);
                 }
+                for a in entry.attachments {
+                    if !target.attachments.iter().any(|t| t.target == a.target) {
+                        target.attachments.push(a);
+                    }
+                }
+                println!("'{}' is merged into [{}] {}", entry.name, i, target.name);
+                merged += 1;
+                continue;
+            }
+            if used.contains(&entry.name) {
+                let key = lint::unique_citekey(&entry.name, &used);
+                println!("'{}' is renamed to '{}'", entry.name, key);
+                entry.rename(&key);
+                renamed += 1;
             }
+            used.insert(entry.name.clone());
+            matcher.insert(&entry, self.entries.len());
+            self.entries.push(entry);
+            added += 1;
+        }
+        println!(
+            "added {} entries ({} renamed), merged {} duplicates from {}",
+            added,
+            renamed,
+            merged,
+            source.display()
+        );
+        self.update_fulltext();
+    }
+
+    // entries are changed, so that lookups scan them until saved
+    // and the search index is synced before the next search
+    fn touch(&mut self) {
+        self.changed = true;
+        self.fingerprints = None;
+    }
+
+    // positions of entries with this DOI or citekey, from the indexes of storage
+    // unless entries are changed since, e.g. in the middle of a script
+    pub fn find(&self, doi_or_key: &str) -> Vec<usize> {
+        let indexed = match (&self.storage, self.changed) {
+            (Some(s), false) => s.lookup(doi_or_key).unwrap_or(None),
+            _ => None,
         };
-        Ok(lib)
+        if let Some(found) = indexed {
+            return found;
+        }
+        (0..self.entries.len())
+            .filter(|&i| self.entries[i].doi == doi_or_key || self.entries[i].name == doi_or_key)
+            .collect()
     }
 
-    pub fn add(&mut self, name: &str, id: &str) {
+    // position of the new entry
+    fn try_add(&mut self, name: &str, id: &str) -> Result<usize, String> {
+        if let Some(i) = self.find(id).first() {
+            return Err(format!("'{}' is already in library as entry {}", id, i));
+        }
         let mut new_entry = Entry::new(name, id);
         let rt = Runtime::new().unwrap();
         match rt.block_on(new_entry.get_bib()) {
-            Ok(()) => self.entries.push(new_entry),
-            Err(e) => println!("Failed to add entry, error: {}", e),
+            Ok(()) => {
+                self.entries.push(new_entry);
+                self.touch();
+            }
+            Err(e) => return Err(format!("Failed to add entry, error: {}", e)),
         };
+        Ok(self.entries.len() - 1)
     }
 
-    pub fn add_batch(&mut self, mut entries: Vec<Entry>) {
-        const CONCURRENT_NUM: usize = 5;
+    // entries which cannot be fetched are reported, and fail the batch after the others are added
+    pub fn add_batch(&mut self, mut entries: Vec<Entry>) -> Result<(), String> {
+        self.touch();
         let pb = ProgressBar::new(entries.len() as u64);
         let rt = Runtime::new().unwrap();
         let results = rt.block_on(async {
@@ -125,131 +401,700 @@ impl Library {
                     pb.inc(1);
                     r
                 })
-                .buffered(CONCURRENT_NUM)
+                .buffered(config::get().concurrency)
                 .collect::<Vec<_>>()
                 .await
         });
         pb.finish_with_message("done");
-        let old_len = self.entries.len();
-        for (entry, result) in entries.into_iter().zip(results.into_iter()) {
+        let (old_len, total) = (self.entries.len(), entries.len());
+        for (entry, result) in entries.into_iter().zip(results) {
             match result {
                 Ok(()) => self.entries.push(entry),
-                Err(e) => println!("add '{}' error: {}", entry.name, e),
+                Err(e) => eprintln!("add '{}' error: {}", entry.name, e),
             }
         }
-        println!("Add {} entries from file", self.entries.len() - old_len);
+        let added = self.entries.len() - old_len;
+        println!("Add {} entries from file", added);
+        self.update_fulltext();
+        match added == total {
+            true => Ok(()),
+            false => Err(format!("{} of {} entries not added", total - added, total)),
+        }
     }
 
-    pub fn del(&mut self, id: usize) {
-        if id < self.entries.len() {
-            self.entries.remove(id);
-        } else {
-            println!("No such id");
+    pub fn del(&mut self, id: usize) -> Result<(), String> {
+        if id >= self.entries.len() {
+            return Err(String::from("No such id"));
         }
+        self.entries.remove(id);
+        self.touch();
+        Ok(())
     }
 
-    pub fn link(&mut self, id: usize, path: PathBuf) {
+    pub fn link(&mut self, id: usize, path: PathBuf) -> Result<(), String> {
         // TODO: download pdf automaticlly
-        match self.entries.get_mut(id) {
-            None => println!("No such id"),
-            Some(e) => e.link(path),
-        };
+        self.entries.get_mut(id).ok_or("No such id")?.link(path);
+        self.update_fulltext();
+        Ok(())
     }
 
-    pub fn view(&self, id: usize) {
-        match self.entries.get(id) {
-            None => println!("No such id"),
-            Some(entry) => match &entry.path {
-                None => println!("No pdf file of this entry"),
-                Some(p) => {
-                    if let Err(e) = utils::view_pdf_file(p) {
-                        println!("View pdf file failed, error {}", e);
-                    }
+    pub fn attach(&mut self, id: usize, kind: AttachmentKind, target: &str) -> Result<(), String> {
+        let entry = self.entries.get_mut(id).ok_or("No such id")?;
+        entry.attach(Attachment::new(kind, target));
+        self.update_fulltext();
+        Ok(())
+    }
+
+    pub fn detach(&mut self, id: usize, n: usize) -> Result<(), String> {
+        let entry = self.entries.get_mut(id).ok_or("No such id")?;
+        if n >= entry.attachments.len() {
+            return Err(String::from("No such attachment"));
+        }
+        let a = entry.attachments.remove(n);
+        println!("detached {}", a);
+        self.update_fulltext();
+        Ok(())
+    }
+
+    pub fn show(&self, id: usize, output: Output) -> Result<(), String> {
+        let entry = self.entries.get(id).ok_or("No such id")?;
+        if output != Output::Table {
+            let opts = ListOptions {
+                output: Some(output),
+                ..ListOptions::default()
+            };
+            record::print_entries(&mut std::iter::once((id, entry)), &opts);
+            return Ok(());
+        }
+        entry.details().iter().for_each(|l| println!("{}", l));
+        Ok(())
+    }
+
+    // edit bibtex of an entry in $EDITOR until it parses,
+    // the citekey and DOI of the entry follow the edited bibtex
+    pub fn edit(&mut self, id: usize) -> Result<(), String> {
+        let entry = self.entries.get(id).ok_or("No such id")?;
+        let dir =
+            utils::create_temp_dir("edit").map_err(|e| format!("Edit failed, error {}", e))?;
+        let path = dir.join("entry.bib");
+        let mut bibtex = entry.bibtex.clone();
+        let result = loop {
+            bibtex = match utils::edit_text(&bibtex, &path) {
+                Ok(b) => b,
+                Err(e) => break Err(format!("Edit failed, error {}", e)),
+            };
+            if bibtex == self.entries[id].bibtex {
+                println!("Entry not changed");
+                break Ok(());
+            }
+            match self.apply_edit(id, &bibtex) {
+                Ok(()) => {
+                    println!("Entry {} updated", self.entries[id].name);
+                    break Ok(());
+                }
+                Err(e) if !utils::confirm(&format!("{}, edit again?", e)) => {
+                    break Err(format!("{}, entry not changed", e))
                 }
-            },
+                Err(_) => {}
+            }
         };
+        let _ = std::fs::remove_dir_all(&dir);
+        self.update_fulltext();
+        result
     }
 
-    pub fn print(&self) {
-        if let Some(p) = &self.path {
-            println!("Current library: {}", p.display());
+    fn apply_edit(&mut self, id: usize, bibtex: &str) -> Result<(), String> {
+        self.touch();
+        let bibs = Bibliography::parse(bibtex).ok_or("Invalid bibtex, syntax error")?;
+        let bib = match bibs.iter().collect::<Vec<_>>()[..] {
+            [bib] => bib.clone(),
+            ref all => return Err(format!("Expect one bibtex entry, found {}", all.len())),
         };
-        utils::print_entries(&mut self.entries.iter().enumerate());
+        if bib.key.is_empty() || bib.key.contains(char::is_whitespace) {
+            return Err(String::from("Invalid citekey"));
+        }
+        let taken = self
+            .entries
+            .iter()
+            .enumerate()
+            .any(|(i, e)| i != id && e.name == bib.key);
+        if taken {
+            return Err(format!("Citekey '{}' is taken by another entry", bib.key));
+        }
+        let entry = &mut self.entries[id];
+        entry.name = bib.key.clone();
+        entry.parse_bibtex(bib.to_bibtex_string())?;
+        // entries added by arXiv id keep it, to download their pdf files
+        if entry.doi.is_empty() || entry.doi.starts_with("10.") {
+            if let Some(doi) = bib.get("doi") {
+                entry.doi = normalize::clean_doi(&doi.format_verbatim());
+            }
+        }
+        Ok(())
     }
 
-    pub fn search(&self, pat: &str, fuzzy: bool) {
-        if let Some(p) = &self.path {
-            println!("Current library: {}", p.display());
+    // view n-th attachment, or the pdf file if n is not given
+    pub fn view(&self, id: usize, n: Option<usize>) -> Result<(), String> {
+        let entry = self.entries.get(id).ok_or("No such id")?;
+        let attachment = match n {
+            Some(n) => entry.attachments.get(n),
+            None => entry
+                .attachments
+                .iter()
+                .find(|a| a.kind == AttachmentKind::Pdf)
+                .or_else(|| entry.attachments.first()),
         };
-        let matcher: Box<dyn utils::Matcher> = if fuzzy {
-            Box::new(SkimMatcherV2::default())
-        } else {
-            Box::new(utils::StrictMatcher)
+        match attachment {
+            None if n.is_some() => Err(String::from("No such attachment")),
+            None => Err(String::from("No pdf file of this entry")),
+            Some(a) => utils::view_file(&a.location())
+                .map_err(|e| format!("View file failed, error {}", e)),
+        }
+    }
+
+    // only tables are headed by the library, other outputs are for scripts
+    fn print_header(&self, output: Output) {
+        if let (Some(p), Output::Table) = (&self.path, output) {
+            println!("Current library: {}", p.display());
+        }
+    }
+
+    pub fn print(&self, opts: &ListOptions) {
+        self.print_header(opts.output());
+        record::print_entries(&mut self.entries.iter().enumerate(), opts);
+    }
+
+    pub fn search(
+        &mut self,
+        pat: &str,
+        fuzzy: bool,
+        exact: bool,
+        fulltext: bool,
+        opts: &ListOptions,
+    ) {
+        self.print_header(opts.output());
+        if fulltext {
+            return self.search_fulltext(pat, opts);
+        }
+        if !fuzzy && !exact {
+            return self.search_index(pat, opts);
+        }
+        let matcher = SkimMatcherV2::default();
+        // exact matches are scored by count of occurrences
+        let score = |bibtex: &str| match exact {
+            true => Some(bibtex.matches(pat).count() as i64).filter(|&n| n > 0),
+            false => matcher.fuzzy_match(bibtex, pat),
         };
         let mut matched = self
             .entries
             .iter()
             .enumerate()
-            .filter_map(|(id, e)| matcher.score(&e.bibtex, pat).map(|s| (s, id, e)))
+            .filter_map(|(id, e)| score(&e.bibtex).map(|s| (s, id, e)))
             .collect::<Vec<_>>();
         matched.sort_by_key(|t| -t.0);
         let mut to_print = matched.into_iter().map(|(_, i, e)| (i, e));
-        utils::print_entries(&mut to_print);
+        record::print_entries(&mut to_print, opts);
     }
 
-    pub fn save(&self) -> Result<(), Box<dyn Error>> {
-        if let Some(p) = &self.path {
-            serde_json::to_writer(&File::create(p)?, self)?;
+    // ranked search through the persistent index of parsed fields
+    fn search_ids(&mut self, query: &str) -> Vec<usize> {
+        let fps = match self.fingerprints.take() {
+            Some(fps) => fps,
+            None => self.index.sync(&self.entries),
+        };
+        let mut ids: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (id, fp) in fps.iter().enumerate() {
+            ids.entry(fp.as_str()).or_default().push(id);
         }
-        Ok(())
+        let mut ranked: Vec<(usize, f64)> = self
+            .index
+            .query(query)
+            .into_iter()
+            .flat_map(|(fp, score)| {
+                let found = ids.get(fp.as_str()).cloned().unwrap_or_default();
+                found.into_iter().map(move |id| (id, score))
+            })
+            .collect();
+        // equal scores in library order
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        self.fingerprints = Some(fps);
+        ranked.into_iter().map(|(id, _)| id).collect()
+    }
+
+    fn search_index(&mut self, query: &str, opts: &ListOptions) {
+        let ids = self.search_ids(query);
+        let mut to_print = ids.into_iter().map(|id| (id, &self.entries[id]));
+        record::print_entries(&mut to_print, opts);
     }
 
-    pub fn gen_bibtex(&self, id: Option<usize>) {
-        match id {
-            None => {
-                // gen all bibtex
-                for entry in self.entries.iter() {
-                    println!("{}", entry.bibtex);
+    // records of other outputs are the matched entries, without pages and context
+    fn search_fulltext(&mut self, pat: &str, opts: &ListOptions) {
+        self.update_fulltext();
+        let mut matched = Vec::new();
+        for (id, entry) in self.entries.iter().enumerate() {
+            for attachment in entry.attachments.iter() {
+                if let Some(meta) = &attachment.meta {
+                    for m in self.fulltext.search(&meta.sha256, pat) {
+                        matched.push((id, entry, m));
+                    }
                 }
             }
-            Some(i) => {
-                // gen for one entry
-                let output = self.entries.get(i).map_or("No such id", |e| &e.bibtex);
-                println!("{}", output);
+        }
+        if opts.output() != Output::Table {
+            let mut ids: Vec<usize> = matched.iter().map(|(id, _, _)| *id).collect();
+            ids.dedup();
+            let mut to_print = ids.into_iter().map(|id| (id, &self.entries[id]));
+            return record::print_entries(&mut to_print, opts);
+        }
+        utils::print_text_matches(&mut matched.into_iter());
+    }
+
+    // extract text of newly linked pdf files, and forget unlinked ones
+    fn update_fulltext(&mut self) {
+        let files = self.unextracted_files();
+        let pb = match files.len() {
+            0 => ProgressBar::hidden(),
+            n => ProgressBar::new(n as u64),
+        };
+        pb.set_message("extracting text");
+        for e in self.extract_fulltext(&files, &pb) {
+            eprintln!("{}", e);
+        }
+    }
+
+    // the same without drawing progress, e.g. over the TUI, returns extraction errors
+    fn update_fulltext_quietly(&mut self) -> Vec<String> {
+        let files = self.unextracted_files();
+        self.extract_fulltext(&files, &ProgressBar::hidden())
+    }
+
+    // linked pdf files with their sha256, whose text is not extracted yet
+    fn unextracted_files(&mut self) -> Vec<(String, PathBuf)> {
+        let mut to_index = Vec::new();
+        for attachment in self
+            .entries
+            .iter_mut()
+            .flat_map(|e| e.attachments.iter_mut())
+        {
+            let path = match attachment.path() {
+                Some(p) if files::check_file(p) == files::FileStatus::Ok => p.to_path_buf(),
+                _ => continue,
+            };
+            if attachment.meta.is_none() {
+                attachment.meta = files::FileMeta::from_path(&path).ok();
+            }
+            if let Some(meta) = &attachment.meta {
+                if !self.fulltext.contains(&meta.sha256) {
+                    to_index.push((meta.sha256.clone(), path));
+                }
+            }
+        }
+        to_index
+    }
+
+    fn extract_fulltext(&mut self, files: &[(String, PathBuf)], pb: &ProgressBar) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (hash, path) in files.iter() {
+            if let Err(e) = self.fulltext.insert(hash, path) {
+                errors.push(format!("extract '{}' error: {}", path.display(), e));
+            }
+            pb.inc(1);
+        }
+        pb.finish_and_clear();
+        let linked = self
+            .entries
+            .iter()
+            .flat_map(|e| e.attachments.iter())
+            .filter_map(|a| a.meta.as_ref().map(|m| m.sha256.as_str()))
+            .collect();
+        self.fulltext.retain(&linked);
+        errors
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(s) = self.storage.as_mut() {
+            s.save(&self.entries)?;
+            self.changed = false;
+        }
+        self.fulltext.save()?;
+        self.fingerprints = Some(self.index.sync(&self.entries));
+        self.index.save()?;
+        Ok(())
+    }
+
+    // stored bibtex is printed as is with `--raw`
+    pub fn gen_bibtex(
+        &self,
+        id: Option<usize>,
+        opts: &normalize::GenOptions,
+    ) -> Result<(), String> {
+        let ids = match id {
+            None => (0..self.entries.len()).collect(),
+            Some(i) => vec![i],
+        };
+        let bibs = self
+            .bibtex(&ids, opts)
+            .map_err(|e| format!("gen error: {}", e))?;
+        bibs.iter().for_each(|b| println!("{}", b));
+        Ok(())
+    }
+
+    fn bibtex(
+        &self,
+        ids: &[usize],
+        opts: &normalize::GenOptions,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let profile = opts.profile(self.path.as_deref().unwrap_or(Path::new("")))?;
+        let mut bibs = Vec::new();
+        for id in ids {
+            let entry = self.entries.get(*id).ok_or("No such id")?;
+            let normalized = profile
+                .as_ref()
+                .and_then(|p| normalize::normalize_bibtex(&entry.bibtex, p));
+            bibs.push(normalized.unwrap_or_else(|| entry.bibtex.clone()));
+        }
+        Ok(bibs)
+    }
+
+    // entries matching a query, or all entries with "*", with their normalized bibtex
+    // and attached files into a zip archive
+    pub fn bundle(&mut self, query: &str, out: PathBuf) -> Result<(), String> {
+        let ids = match query {
+            "*" => (0..self.entries.len()).collect(),
+            q => self.search_ids(q),
+        };
+        if ids.is_empty() {
+            return Err(format!("No entries match '{}'", query));
+        }
+        let entries: Vec<&Entry> = ids.iter().map(|&i| &self.entries[i]).collect();
+        let profile = normalize::Profile::default();
+        let bibtex: Vec<String> = entries
+            .iter()
+            .map(|e| {
+                normalize::normalize_bibtex(&e.bibtex, &profile).unwrap_or_else(|| e.bibtex.clone())
+            })
+            .collect();
+        let n = bundle::write_bundle(&out, &entries, &bibtex.join("\n"))
+            .map_err(|e| format!("bundle error: {}", e))?;
+        println!(
+            "bundled {} entries and {} files into {}",
+            entries.len(),
+            n,
+            out.display()
+        );
+        Ok(())
+    }
+
+    // extract files of a bundle into folder, and merge its entries into library
+    pub fn unbundle(&mut self, path: PathBuf, folder: Option<PathBuf>) -> Result<(), String> {
+        let folder = folder.unwrap_or_else(|| self.managed_folder());
+        let entries =
+            bundle::read_bundle(&path, &folder).map_err(|e| format!("unbundle error: {}", e))?;
+        self.merge_entries(entries, &path);
+        Ok(())
+    }
+
+    // items of a Zotero database, with their tags, collections, notes and files
+    pub fn import_zotero(&mut self, path: PathBuf) -> Result<(), String> {
+        let entries = zotero::read_zotero(&path).map_err(|e| format!("import error: {}", e))?;
+        self.merge_entries(entries, &path);
+        Ok(())
+    }
+
+    // render chosen entries, a search result or the entire library
+    // with a builtin style name or a CSL style file
+    pub fn cite(
+        &mut self,
+        ids: Vec<usize>,
+        query: Option<String>,
+        style: &str,
+        format: render::OutputFormat,
+    ) -> Result<(), String> {
+        let style = match render::builtin_style(style) {
+            Some(s) => s.to_string(),
+            None => std::fs::read_to_string(style)
+                .map_err(|e| format!("No such style '{}', error: {}", style, e))?,
+        };
+        let ids = match query {
+            Some(q) => self.search_ids(&q),
+            None if ids.is_empty() => (0..self.entries.len()).collect(),
+            None => ids,
+        };
+        let mut items = Vec::new();
+        for id in ids {
+            match self.entries.get(id).map(|e| (e, e.parsed())) {
+                Some((e, Some(bib))) => items.push(render::Item::from_entry(&e.name, &bib)),
+                Some((e, None)) => eprintln!("Invalid bibtex of entry '{}'", e.name),
+                None => return Err(format!("No such id {}", id)),
+            }
+        }
+        let refs = render::render(&style, &items, format)
+            .map_err(|e| format!("Render failed, error: {}", e))?;
+        println!("{}", render::wrap_document(&refs, format));
+        Ok(())
+    }
+
+    // print issues of entries, returns the number of issues not fixed
+    pub fn lint(&mut self, opts: &lint::LintOptions) -> usize {
+        self.touch();
+        let issues = lint::lint(&mut self.entries, opts);
+        let unfixed = issues.iter().filter(|i| !i.fixed).count();
+        if opts.json {
+            match serde_json::to_string_pretty(&issues) {
+                Ok(s) => println!("{}", s),
+                Err(e) => println!("lint error: {}", e),
             }
+            return unfixed;
         }
+        for i in issues.iter() {
+            let fixed = if i.fixed { " (fixed)" } else { "" };
+            println!(
+                "[{}] {}: {}: {}{}",
+                i.id, i.citekey, i.check, i.message, fixed
+            );
+        }
+        println!("{} issues, {} fixed", issues.len(), issues.len() - unfixed);
+        unfixed
     }
 
     pub fn load_bibtex(&mut self, entries: Vec<Entry>) {
+        self.touch();
         let len = entries.len();
-        self.entries.extend(entries.into_iter());
+        self.entries.extend(entries);
         println!("load {} entries from file", len);
     }
 
-    pub fn download(&mut self, folder: Option<PathBuf>) {
-        let path = match folder {
-            Some(p) => p,
-            None => self.path.clone().unwrap().parent().unwrap().to_path_buf(),
-        };
+    // folder where pdf files of this library are kept by default
+    fn managed_folder(&self) -> PathBuf {
+        if let Some(dir) = &config::get().download_dir {
+            return dir.clone();
+        }
+        match self.path.as_ref().and_then(|p| p.parent()) {
+            Some(p) if p != Path::new("") => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    // pdf files downloaded before a failure are still linked
+    pub fn download(&mut self, folder: Option<PathBuf>) -> Result<(), String> {
+        let path = folder.unwrap_or_else(|| self.managed_folder());
         let ids: Vec<_> = self
             .entries
             .iter()
-            .filter(|e| e.path.is_none())
+            .filter(|e| e.pdf().is_none())
             .map(|e| &e.doi[..])
             .collect();
         let rt = Runtime::new().unwrap();
-        if let Err(e) = rt.block_on(downloader::download_pdfs(ids, path.clone())) {
-            println!("{}", e);
-        }
+        let result = rt.block_on(downloader::download_pdfs(ids, path.clone()));
+        self.link_downloaded(&path);
+        self.update_fulltext();
+        result.map_err(|e| e.to_string())
+    }
+
+    // link pdf files downloaded into folder to entries without one
+    fn link_downloaded(&mut self, folder: &Path) {
         self.entries
             .iter_mut()
-            .filter(|e| e.path.is_none())
+            .filter(|e| e.pdf().is_none())
             .for_each(|e| {
-                let mut file = path.clone();
-                file.push(format!("{}.pdf", e.doi));
+                let file = folder.join(format!("{}.pdf", e.doi));
                 if file.exists() {
                     e.link(file);
                 }
             });
     }
+
+    pub fn check_files(&mut self, folder: Option<PathBuf>, dedup: bool) {
+        let folder = folder.unwrap_or_else(|| self.managed_folder());
+        let pdfs = files::find_pdfs(&folder);
+        let linked: HashSet<PathBuf> = self
+            .entries
+            .iter()
+            .flat_map(|e| e.attachments.iter().filter_map(|a| a.path()))
+            .map(files::canonical)
+            .collect();
+        // pdf files in folder which are not linked by any entry
+        let mut orphans: Vec<(PathBuf, Option<String>)> = pdfs
+            .into_iter()
+            .filter(|pdf| !linked.contains(&files::canonical(pdf)))
+            .map(|pdf| (pdf, None))
+            .collect();
+        let (mut broken, mut fixed) = (0, 0);
+        for (id, entry) in self.entries.iter_mut().enumerate() {
+            for (n, attachment) in entry.attachments.iter_mut().enumerate() {
+                let path = match attachment.path() {
+                    Some(p) => p.to_path_buf(),
+                    None => continue,
+                };
+                let status = match files::check_file(&path) {
+                    // only pdf attachments have to be pdf files
+                    files::FileStatus::NotPdf if attachment.kind != AttachmentKind::Pdf => {
+                        files::FileStatus::Ok
+                    }
+                    status => status,
+                };
+                if status == files::FileStatus::Ok {
+                    if let Some(meta) = attachment.meta.as_ref().filter(|m| !m.matches(&path)) {
+                        broken += 1;
+                        println!(
+                            "[{}.{}] {}: {} changed since linking (recorded {} bytes)",
+                            id,
+                            n,
+                            entry.name,
+                            path.display(),
+                            meta.size
+                        );
+                    }
+                    continue;
+                }
+                broken += 1;
+                println!(
+                    "[{}.{}] {}: {} is {}",
+                    id,
+                    n,
+                    entry.name,
+                    path.display(),
+                    status
+                );
+                if status != files::FileStatus::Missing {
+                    continue;
+                }
+                // try to re-find moved file by its content hash, then by its filename
+                let by_hash = attachment.meta.as_ref().and_then(|meta| {
+                    orphans.iter_mut().position(|(pdf, hash)| {
+                        hash.get_or_insert_with(|| files::sha256_file(pdf).unwrap_or_default())
+                            == &meta.sha256
+                    })
+                });
+                let found = by_hash.or_else(|| {
+                    orphans.iter().position(|(pdf, _)| {
+                        pdf.file_name() == path.file_name()
+                            && files::check_file(pdf) == files::FileStatus::Ok
+                    })
+                });
+                if let Some(i) = found {
+                    let (new_path, _) = orphans.remove(i);
+                    let new_path = new_path.canonicalize().unwrap_or(new_path);
+                    println!("    relinked to {}", new_path.display());
+                    attachment.relink(new_path);
+                    fixed += 1;
+                }
+            }
+        }
+        for (pdf, _) in orphans.iter() {
+            println!("orphan pdf file: {}", pdf.display());
+        }
+        self.check_duplicates(dedup);
+        self.update_fulltext();
+        println!(
+            "{} broken links ({} fixed), {} orphan pdf files in {}",
+            broken,
+            fixed,
+            orphans.len(),
+            folder.display()
+        );
+    }
+
+    // report attachments linked to different copies of the same file
+    fn check_duplicates(&mut self, dedup: bool) {
+        let mut first: HashMap<String, PathBuf> = HashMap::new();
+        for (id, entry) in self.entries.iter_mut().enumerate() {
+            for (n, attachment) in entry.attachments.iter_mut().enumerate() {
+                let (path, hash) = match (attachment.path(), &attachment.meta) {
+                    (Some(p), Some(m)) => (p.to_path_buf(), m.sha256.clone()),
+                    _ => continue,
+                };
+                let orig_path = first.entry(hash).or_insert_with(|| path.clone());
+                if files::same_file(orig_path, &path) {
+                    continue;
+                }
+                println!(
+                    "[{}.{}] {} is identical to {}",
+                    id,
+                    n,
+                    path.display(),
+                    orig_path.display()
+                );
+                if dedup {
+                    attachment.relink(orig_path.clone());
+                    println!("    relinked to {}", orig_path.display());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn entry(name: &str, doi: &str) -> Entry {
+        let mut entry = Entry::new(name, doi);
+        entry.bibtex = format!("@article{{{}, title = {{A paper}}, year = {{2013}}}}", name);
+        entry
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        let mut lib = Library::default();
+        lib.entries.push(entry("swan", "10.1145/2486001.2486012"));
+        lib.entries.push(entry("rl", "1904.12901"));
+
+        let err = lib
+            .apply_edit(0, "@article{rl, title = {A paper}}")
+            .unwrap_err();
+        assert!(err.contains("taken"), "{}", err);
+        let err = lib.apply_edit(0, "% nothing").unwrap_err();
+        assert!(err.contains("found 0"), "{}", err);
+        let two = "@article{a, title = {A}}\n@article{b, title = {B}}";
+        let err = lib.apply_edit(0, two).unwrap_err();
+        assert!(err.contains("found 2"), "{}", err);
+        assert!(lib.apply_edit(0, "@article{a b, title = {A}}").is_err());
+        assert_eq!(lib.entries[0].name, "swan");
+
+        let edited = "@article{b4, title = {B4}, doi = {https://doi.org/10.1145/2534169.2486019}}";
+        lib.apply_edit(0, edited).unwrap();
+        assert_eq!(lib.entries[0].name, "b4");
+        assert_eq!(lib.entries[0].title, "B4");
+        assert_eq!(lib.entries[0].doi, "10.1145/2534169.2486019");
+        // entries added by arXiv id keep it
+        let edited = "@article{rl, title = {RL}, doi = {10.1145/3341302.3342080}}";
+        lib.apply_edit(1, edited).unwrap();
+        assert_eq!(lib.entries[1].doi, "1904.12901");
+    }
+
+    #[test]
+    fn test_find() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("refhelper-find-{}.db", std::process::id()));
+        let mut lib = Library::from_path(path.clone())?;
+        lib.entries.push(entry("swan", "10.1145/2486001.2486012"));
+        lib.entries.push(entry("rl", "1904.12901"));
+        lib.save()?;
+        // indexed lookups, then scans until saved again
+        assert_eq!(lib.find("1904.12901"), vec![1]);
+        lib.del(0)?;
+        assert_eq!(lib.find("1904.12901"), vec![0]);
+        lib.save()?;
+        assert_eq!(lib.find("rl"), vec![0]);
+        assert!(lib.find("swan").is_empty());
+        for ext in ["db", "fulltext.json", "index.json"] {
+            let _ = std::fs::remove_file(path.with_extension(ext));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_search_ids() -> Result<(), String> {
+        let mut lib = Library::default();
+        for name in ["c", "a", "d", "b"] {
+            lib.entries.push(entry(name, ""));
+        }
+        // equal scores in library order, also after a change
+        assert_eq!(lib.search_ids("2013"), vec![0, 1, 2, 3]);
+        lib.del(1)?;
+        assert_eq!(lib.search_ids("2013"), vec![0, 1, 2]);
+        assert_eq!(lib.search_ids("name:d"), vec![1]);
+        Ok(())
+    }
 }
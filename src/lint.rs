@@ -0,0 +1,470 @@
+// quality checks of library entries, with safe repairs in `--fix` mode
+use biblatex::{Chunk, ChunksExt};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use structopt::StructOpt;
+use tokio::runtime::Runtime;
+
+use crate::config;
+use crate::downloader::{ArxivDownloader, DOIDownloader};
+use crate::normalize::clean_doi;
+use crate::Entry;
+
+// citekeys of entries added without one, e.g. from Zotero or the browser
+pub const DEFAULT_TEMPLATE: &str = "{author}{year}{title}";
+
+// title words skipped by `{title}` in citekey templates
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "on", "of", "the", "for", "in", "to", "and", "with",
+];
+
+// required fields of bibtex entry types, "a|b" means either one,
+// biblatex names are accepted as well
+const REQUIRED_FIELDS: &[(&str, &[&str])] = &[
+    ("article", &["author", "title", "journal", "year"]),
+    ("book", &["author|editor", "title", "publisher", "year"]),
+    ("booklet", &["title"]),
+    (
+        "inbook",
+        &[
+            "author|editor",
+            "title",
+            "chapter|pages",
+            "publisher",
+            "year",
+        ],
+    ),
+    (
+        "incollection",
+        &["author", "title", "booktitle", "publisher", "year"],
+    ),
+    ("inproceedings", &["author", "title", "booktitle", "year"]),
+    ("manual", &["title"]),
+    ("mastersthesis", &["author", "title", "school", "year"]),
+    ("phdthesis", &["author", "title", "school", "year"]),
+    ("proceedings", &["title", "year"]),
+    ("techreport", &["author", "title", "institution", "year"]),
+    ("unpublished", &["author", "title", "note"]),
+];
+
+#[derive(StructOpt, Debug)]
+pub struct LintOptions {
+    /// Print issues as JSON
+    #[structopt(long)]
+    pub json: bool,
+    /// Apply safe automatic repairs
+    #[structopt(long)]
+    pub fix: bool,
+    /// Resolve DOIs and look up published versions of arXiv papers online
+    #[structopt(long)]
+    pub online: bool,
+    /// Expected citekey, e.g. `{author}{year}{title}`, default is `citekey_template` of config
+    #[structopt(long)]
+    pub template: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Issue {
+    pub id: usize,
+    pub citekey: String,
+    pub check: &'static str,
+    pub message: String,
+    pub fixed: bool,
+}
+
+pub fn lint(entries: &mut [Entry], opts: &LintOptions) -> Vec<Issue> {
+    let mut issues = Vec::new();
+    let mut issue = |id: usize, e: &Entry, check, message: String, fixed| {
+        issues.push(Issue {
+            id,
+            citekey: e.name.clone(),
+            check,
+            message,
+            fixed,
+        })
+    };
+    let mut published: Vec<(usize, String)> = Vec::new();
+    // `--template`, or the one set in config
+    let template = opts
+        .template
+        .as_ref()
+        .or(config::get().citekey_template.as_ref());
+    for (id, e) in entries.iter_mut().enumerate() {
+        // left by `merge`, resolved by removing the field
+        if let Some(conflicts) = e.extra.get("conflicts").and_then(|c| c.as_object()) {
+            let fields: Vec<&str> = conflicts.keys().map(|k| k.as_str()).collect();
+            let message = format!("unresolved merge conflict in {}", fields.join(", "));
+            issue(id, e, "merge-conflict", message, false);
+        }
+        let bib = match e.parsed() {
+            Some(b) => b,
+            None => {
+                issue(
+                    id,
+                    e,
+                    "invalid-bibtex",
+                    String::from("bibtex is empty or invalid"),
+                    false,
+                );
+                continue;
+            }
+        };
+        let missing = missing_fields(&bib);
+        if !missing.is_empty() {
+            let message = format!("{} is missing {}", bib.entry_type, missing.join(", "));
+            issue(id, e, "missing-field", message, false);
+        }
+
+        // DOI of entry and doi field of bibtex
+        let doi = clean_doi(&e.doi);
+        if doi != e.doi && is_doi(&doi) {
+            issue(
+                id,
+                e,
+                "invalid-doi",
+                format!("DOI '{}' should be '{}'", e.doi, doi),
+                opts.fix,
+            );
+            if opts.fix {
+                e.doi = doi;
+            }
+        }
+        if !e.doi.is_empty() && !is_doi(&e.doi) && !is_arxiv(&e.doi) {
+            issue(
+                id,
+                e,
+                "invalid-doi",
+                format!("'{}' is neither a DOI nor an arXiv id", e.doi),
+                false,
+            );
+        }
+        if let Some(field) = bib.get("doi").map(|c| c.format_verbatim()) {
+            let doi = clean_doi(&field);
+            if !is_doi(&doi) {
+                issue(
+                    id,
+                    e,
+                    "invalid-doi",
+                    format!("doi field '{}' is not a DOI", field),
+                    false,
+                );
+            } else if doi != field {
+                issue(
+                    id,
+                    e,
+                    "invalid-doi",
+                    format!("doi field '{}' should be '{}'", field, doi),
+                    opts.fix,
+                );
+                if opts.fix {
+                    let mut bib = bib.clone();
+                    bib.set("doi", vec![Chunk::Verbatim(doi.clone())]);
+                    e.bibtex = bib.to_bibtex_string();
+                }
+            }
+            // arXiv entries whose bibtex already knows the published version
+            if is_arxiv(&e.doi) && !doi.starts_with("10.48550/") && is_doi(&doi) {
+                published.push((id, doi));
+            }
+        }
+
+        if let Some(template) = template {
+            let expected = citekey(&bib, template);
+            // a trailing letter is allowed to tell apart papers of the same author and year
+            let matched = e.name == expected
+                || e.name
+                    .strip_prefix(expected.as_str())
+                    .is_some_and(|s| s.len() == 1 && s.chars().all(|c| c.is_ascii_lowercase()));
+            if !matched {
+                issue(
+                    id,
+                    e,
+                    "citekey-template",
+                    format!("citekey should be '{}'", expected),
+                    false,
+                );
+            }
+        }
+
+        let letters: Vec<char> = e.title.chars().filter(|c| c.is_alphabetic()).collect();
+        if letters.len() > 3
+            && e.title.split_whitespace().count() > 1
+            && letters.iter().all(|c| c.is_uppercase())
+        {
+            issue(
+                id,
+                e,
+                "all-caps-title",
+                format!("title '{}' is all caps", e.title),
+                false,
+            );
+        }
+    }
+
+    let rt = match opts.online {
+        true => Some(Runtime::new().unwrap()),
+        false => None,
+    };
+    if let Some(rt) = &rt {
+        let (unresolved, found) = rt.block_on(check_online(entries, &published));
+        for (id, doi) in unresolved {
+            let e = &entries[id];
+            issue(
+                id,
+                e,
+                "unresolvable-doi",
+                format!("DOI '{}' does not resolve", doi),
+                false,
+            );
+        }
+        published.extend(found);
+    }
+    // the bibtex of the preprint is replaced as well, so it is only fixed online
+    let fetched = match (&rt, opts.fix) {
+        (Some(rt), true) => rt.block_on(fetch_published(entries, &published)),
+        _ => published
+            .iter()
+            .map(|_| Err(String::from("fixed with --online")))
+            .collect(),
+    };
+    for ((id, doi), fetched) in published.into_iter().zip(fetched) {
+        let e = &mut entries[id];
+        let message = format!("arXiv {} is published as {}", e.doi, doi);
+        match fetched {
+            Ok(published) if opts.fix => {
+                issue(id, e, "published-version", message, true);
+                *e = published;
+            }
+            Err(err) if opts.fix => {
+                let message = format!("{}, not fixed: {}", message, err);
+                issue(id, e, "published-version", message, false);
+            }
+            _ => issue(id, e, "published-version", message, false),
+        }
+    }
+
+    // later entries with a used citekey get a suffix, e.g. smith2013 -> smith2013b
+    let mut used: HashSet<String> = entries.iter().map(|e| e.name.clone()).collect();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (id, e) in entries.iter_mut().enumerate() {
+        let first = match seen.get(&e.name) {
+            Some(&first) => first,
+            None => {
+                seen.insert(e.name.clone(), id);
+                continue;
+            }
+        };
+        let message = format!("citekey is also used by entry {}", first);
+        issue(id, e, "duplicate-citekey", message, opts.fix);
+        if opts.fix {
+            let key = unique_citekey(&e.name, &used);
+            used.insert(key.clone());
+            e.rename(&key);
+        }
+    }
+    issues.sort_by_key(|i| i.id);
+    issues
+}
+
+// a citekey not used yet, e.g. smith2013 -> smith2013b
+pub fn unique_citekey(name: &str, used: &HashSet<String>) -> String {
+    (2..)
+        .map(|n: u32| match std::char::from_u32('a' as u32 + n - 1) {
+            Some(c) if c <= 'z' => format!("{}{}", name, c),
+            _ => format!("{}_{}", name, n),
+        })
+        .find(|k| !used.contains(k))
+        .unwrap()
+}
+
+// DOIs which do not resolve, and published DOIs of arXiv entries
+async fn check_online(
+    entries: &[Entry],
+    known: &[(usize, String)],
+) -> (Vec<(usize, String)>, Vec<(usize, String)>) {
+    let dois: Vec<(usize, String)> = entries
+        .iter()
+        .enumerate()
+        .flat_map(|(id, e)| {
+            let field = e
+                .parsed()
+                .and_then(|b| b.get("doi").map(|c| clean_doi(&c.format_verbatim())));
+            vec![Some(clean_doi(&e.doi)), field]
+                .into_iter()
+                .flatten()
+                .filter(|d| is_doi(d))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .map(move |d| (id, d))
+        })
+        .collect();
+    let unresolved = stream::iter(dois)
+        .map(|(id, doi)| async move {
+            // network errors are not reported as unresolvable
+            match DOIDownloader::resolves(&doi).await {
+                Ok(false) => Some((id, doi)),
+                _ => None,
+            }
+        })
+        .buffered(config::get().concurrency)
+        .filter_map(|r| async { r })
+        .collect::<Vec<_>>()
+        .await;
+    let arxiv = entries
+        .iter()
+        .enumerate()
+        .filter(|(id, e)| is_arxiv(&e.doi) && !known.iter().any(|(k, _)| k == id));
+    let found = stream::iter(arxiv)
+        .map(|(id, e)| async move {
+            match ArxivDownloader::arxiv2doi(&e.doi).await {
+                Ok(Some(doi)) => Some((id, doi)),
+                _ => None,
+            }
+        })
+        .buffered(config::get().concurrency)
+        .filter_map(|r| async { r })
+        .collect::<Vec<_>>()
+        .await;
+    (unresolved, found)
+}
+
+// entries with bibtex of published versions, keeping citekeys, tags and attachments
+async fn fetch_published(
+    entries: &[Entry],
+    published: &[(usize, String)],
+) -> Vec<Result<Entry, String>> {
+    stream::iter(published)
+        .map(|(id, doi)| async move {
+            let mut e = entries[*id].clone();
+            e.doi = doi.clone();
+            e.get_bib().await.map_err(|err| err.to_string())?;
+            e.set_tags(&entries[*id].tags())?;
+            Ok(e)
+        })
+        .buffered(config::get().concurrency)
+        .collect()
+        .await
+}
+
+fn missing_fields(bib: &biblatex::Entry) -> Vec<&'static str> {
+    let entry_type = bib.entry_type.to_bibtex().to_string();
+    let required = REQUIRED_FIELDS
+        .iter()
+        .find(|(t, _)| *t == entry_type)
+        .map_or(&[][..], |(_, f)| f);
+    let has = |field: &str| {
+        let alias = match field {
+            "journal" => "journaltitle",
+            "year" => "date",
+            "school" => "institution",
+            f => f,
+        };
+        [field, alias].iter().any(|f| {
+            bib.get(f)
+                .is_some_and(|c| !c.format_verbatim().trim().is_empty())
+        })
+    };
+    required
+        .iter()
+        .filter(|f| !f.split('|').any(&has))
+        .copied()
+        .collect()
+}
+
+// e.g. 10.1145/2486001.2486012
+fn is_doi(doi: &str) -> bool {
+    match doi.strip_prefix("10.").and_then(|d| d.split_once('/')) {
+        Some((registrant, suffix)) => {
+            (4..=9).contains(&registrant.len())
+                && registrant.chars().all(|c| c.is_ascii_digit() || c == '.')
+                && !suffix.is_empty()
+                && !suffix.chars().any(char::is_whitespace)
+        }
+        None => false,
+    }
+}
+
+// e.g. 1904.12901v2 or cs/0601001
+fn is_arxiv(id: &str) -> bool {
+    let id = id.trim_start_matches("arXiv:");
+    let (number, _) = id.split_once('v').unwrap_or((id, ""));
+    match number.split_once('.') {
+        Some((a, b)) if !number.contains('/') => {
+            a.len() == 4
+                && (4..=5).contains(&b.len())
+                && (a.to_owned() + b).chars().all(|c| c.is_ascii_digit())
+        }
+        _ => number
+            .split_once('/')
+            .is_some_and(|(_, n)| n.len() == 7 && n.chars().all(|c| c.is_ascii_digit())),
+    }
+}
+
+// expand `{author}`, `{year}` and `{title}` with the first author's surname,
+// the year and the first significant title word
+pub fn citekey(bib: &biblatex::Entry, template: &str) -> String {
+    let simplify = |s: &str| -> String { crate::index::tokenize(s).join("") };
+    let author = bib
+        .author()
+        .unwrap_or_default()
+        .first()
+        .map_or(String::new(), |p| simplify(&p.name));
+    let year = match bib.date().map(|d| d.value) {
+        Some(biblatex::DateValue::At(d)) => d.year.to_string(),
+        _ => String::new(),
+    };
+    let title = bib.title().map(|t| t.format_verbatim()).unwrap_or_default();
+    let title = crate::index::tokenize(&title)
+        .into_iter()
+        .find(|w| !STOP_WORDS.contains(&w.as_str()))
+        .unwrap_or_default();
+    template
+        .replace("{author}", &author)
+        .replace("{year}", &year)
+        .replace("{title}", &title)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_lint() {
+        let mut e1 = Entry::new("swan", "https://doi.org/10.1145/2486001.2486012");
+        e1.bibtex = String::from(
+            "@inproceedings{swan, title = {ACHIEVING HIGH UTILIZATION}, \
+             author = {Hong, Chi-Yao}, year = {2013}, booktitle = {SIGCOMM}}",
+        );
+        e1.title = String::from("ACHIEVING HIGH UTILIZATION");
+        let mut e2 = Entry::new("swan", "1904.12901");
+        e2.bibtex = String::from(
+            "@article{swan, title = {Challenges}, author = {Doe, J.}, year = {2019}, \
+             doi = {10.1007/s10994-021-05961-4}}",
+        );
+        let mut entries = vec![e1, e2];
+        let opts = LintOptions {
+            json: false,
+            fix: true,
+            online: false,
+            template: Some(String::from("{author}{year}{title}")),
+        };
+        let issues = lint(&mut entries, &opts);
+        let checks: Vec<_> = issues.iter().map(|i| (i.id, i.check, i.fixed)).collect();
+        assert!(checks.contains(&(0, "invalid-doi", true)));
+        assert!(checks.contains(&(0, "citekey-template", false)));
+        assert!(checks.contains(&(0, "all-caps-title", false)));
+        assert!(checks.contains(&(1, "missing-field", false)));
+        // bibtex of the published version is only fetched with `--online`
+        assert!(checks.contains(&(1, "published-version", false)));
+        assert!(checks.contains(&(1, "duplicate-citekey", true)));
+        assert_eq!(entries[0].doi, "10.1145/2486001.2486012");
+        assert_eq!(entries[1].doi, "1904.12901");
+        assert_eq!(entries[1].name, "swanb");
+        assert!(entries[1].bibtex.contains("swanb"));
+        assert_eq!(
+            citekey(&entries[0].parsed().unwrap(), "{author}{year}{title}"),
+            "hong2013achieving"
+        );
+    }
+}
@@ -0,0 +1,481 @@
+// language server over stdio for citekeys in LaTeX `\cite{a,b}` and pandoc `[@a; @b]`:
+// completion, hover, go to definition (the pdf file or the entry in library) and
+// diagnostics of unknown citekeys, the library is reloaded when its file changes
+use biblatex::ChunksExt;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::storage;
+use crate::Entry;
+
+// a citekey in a line, columns are char indices
+struct Key {
+    start: usize,
+    end: usize,
+    name: String,
+}
+
+// arguments of a cite command or a pandoc citation,
+// from after the opening brace or bracket to the closing one or end of line
+struct Region {
+    start: usize,
+    end: usize,
+    keys: Vec<Key>,
+}
+
+struct Server {
+    lib_path: PathBuf,
+    entries: Vec<Entry>,
+    modified: Option<SystemTime>,
+    docs: HashMap<String, String>,
+}
+
+pub fn run(lib_path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut server = Server {
+        lib_path: lib_path.to_path_buf(),
+        entries: vec![],
+        modified: None,
+        docs: HashMap::new(),
+    };
+    server.reload()?;
+    let mut reader = BufReader::new(io::stdin());
+    let mut out = io::stdout();
+    while let Some(msg) = read_message(&mut reader)? {
+        let method = msg["method"].as_str().unwrap_or("");
+        if method == "exit" {
+            break;
+        }
+        if let Err(e) = server.reload() {
+            eprintln!("reload {} error: {}", server.lib_path.display(), e);
+        }
+        let id = msg.get("id").cloned();
+        let params = &msg["params"];
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": {"triggerCharacters": ["{", ",", "@"]},
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                },
+                "serverInfo": {"name": "refhelper", "version": env!("CARGO_PKG_VERSION")},
+            })),
+            "shutdown" => Some(Value::Null),
+            "textDocument/didOpen" => {
+                let doc = &params["textDocument"];
+                server.open(&mut out, doc["uri"].as_str(), doc["text"].as_str())?;
+                None
+            }
+            "textDocument/didChange" => {
+                // full sync, the last change has the whole text
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|c| c.last())
+                    .and_then(|c| c["text"].as_str());
+                server.open(&mut out, params["textDocument"]["uri"].as_str(), text)?;
+                None
+            }
+            "textDocument/didClose" => {
+                if let Some(uri) = params["textDocument"]["uri"].as_str() {
+                    server.docs.remove(uri);
+                    publish(&mut out, uri, vec![])?;
+                }
+                None
+            }
+            "textDocument/completion" => Some(server.complete(params)),
+            "textDocument/hover" => Some(server.hover(params)),
+            "textDocument/definition" => Some(server.definition(params)),
+            _ => None,
+        };
+        match (id, result) {
+            (Some(id), Some(result)) => write_message(
+                &mut out,
+                &json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            )?,
+            (Some(id), None) => write_message(
+                &mut out,
+                &json!({"jsonrpc": "2.0", "id": id,
+                    "error": {"code": -32601, "message": format!("unknown method {}", method)}}),
+            )?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+impl Server {
+    // read library again if its file changed, diagnostics of open documents may change
+    fn reload(&mut self) -> Result<(), Box<dyn Error>> {
+        let modified = std::fs::metadata(&self.lib_path)?.modified().ok();
+        if modified.is_some() && modified == self.modified {
+            return Ok(());
+        }
+        // without migrating or printing, stdout is for messages to the editor
+        self.entries = storage::read_entries(&self.lib_path)?;
+        self.modified = modified;
+        let mut out = io::stdout();
+        for (uri, text) in self.docs.iter() {
+            publish(&mut out, uri, self.diagnostics(text))?;
+        }
+        Ok(())
+    }
+
+    fn open(
+        &mut self,
+        out: &mut impl Write,
+        uri: Option<&str>,
+        text: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        if let (Some(uri), Some(text)) = (uri, text) {
+            publish(out, uri, self.diagnostics(text))?;
+            self.docs.insert(uri.to_owned(), text.to_owned());
+        }
+        Ok(())
+    }
+
+    fn diagnostics(&self, text: &str) -> Vec<Value> {
+        let mut diagnostics = Vec::new();
+        for (n, line) in lines(text).enumerate() {
+            for key in regions(&line).into_iter().flat_map(|r| r.keys) {
+                if key.name.is_empty() || self.entry(&key.name).is_some() {
+                    continue;
+                }
+                diagnostics.push(json!({
+                    "range": range(&line, n, key.start, key.end),
+                    "severity": 2,
+                    "source": "refhelper",
+                    "message": format!("unknown citekey '{}'", key.name),
+                }));
+            }
+        }
+        diagnostics
+    }
+
+    // line, chars of line and char index of cursor in a request
+    fn position(&self, params: &Value) -> Option<(usize, Vec<char>, usize)> {
+        let text = self.docs.get(params["textDocument"]["uri"].as_str()?)?;
+        let n = params["position"]["line"].as_u64()? as usize;
+        let line = lines(text).nth(n)?;
+        let col = from_utf16(&line, params["position"]["character"].as_u64()? as usize);
+        Some((n, line, col))
+    }
+
+    fn complete(&self, params: &Value) -> Value {
+        let (n, line, col) = match self.position(params) {
+            Some(p) => p,
+            None => return json!([]),
+        };
+        let key = regions(&line)
+            .into_iter()
+            .filter(|r| r.start <= col && col <= r.end)
+            .flat_map(|r| r.keys)
+            .find(|k| k.start <= col && col <= k.end);
+        let key = match key {
+            Some(k) => k,
+            None => return json!([]),
+        };
+        let items: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|e| {
+                json!({
+                    "label": e.name,
+                    "kind": 18,
+                    "detail": e.title,
+                    "documentation": {"kind": "markdown", "value": summary(e)},
+                    "textEdit": {"range": range(&line, n, key.start, key.end), "newText": e.name},
+                })
+            })
+            .collect();
+        json!(items)
+    }
+
+    // citekey under cursor and its entry
+    fn key_at(&self, params: &Value) -> Option<(Value, &Entry)> {
+        let (n, line, col) = self.position(params)?;
+        let key = regions(&line)
+            .into_iter()
+            .flat_map(|r| r.keys)
+            .find(|k| k.start <= col && col <= k.end && !k.name.is_empty())?;
+        let entry = self.entry(&key.name)?;
+        Some((range(&line, n, key.start, key.end), entry))
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        match self.key_at(params) {
+            Some((range, entry)) => json!({
+                "contents": {"kind": "markdown", "value": summary(entry)},
+                "range": range,
+            }),
+            None => Value::Null,
+        }
+    }
+
+    // the pdf file of entry, or the entry in a JSON library
+    fn definition(&self, params: &Value) -> Value {
+        let entry = match self.key_at(params) {
+            Some((_, e)) => e,
+            None => return Value::Null,
+        };
+        let zero =
+            json!({"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 0}});
+        if let Some(uri) = entry.pdf().and_then(file_uri) {
+            return json!({"uri": uri, "range": zero});
+        }
+        let pattern = format!("\"name\": {}", json!(entry.name));
+        let line = std::fs::read_to_string(&self.lib_path)
+            .ok()
+            .and_then(|s| s.lines().position(|l| l.trim_start().starts_with(&pattern)));
+        match (line, file_uri(&self.lib_path)) {
+            (Some(n), Some(uri)) => json!({"uri": uri, "range": {
+                "start": {"line": n, "character": 0},
+                "end": {"line": n, "character": 0},
+            }}),
+            _ => Value::Null,
+        }
+    }
+
+    fn entry(&self, name: &str) -> Option<&Entry> {
+        self.entries.iter().find(|e| e.name == name)
+    }
+}
+
+// markdown of title, authors, year and venue
+fn summary(entry: &Entry) -> String {
+    let mut text = format!("**{}**", entry.title);
+    if let Some(bib) = entry.parsed() {
+        let authors: Vec<String> = bib
+            .author()
+            .unwrap_or_default()
+            .iter()
+            .map(|p| format!("{} {}", p.given_name, p.name).trim().to_owned())
+            .collect();
+        let year = match bib.date().map(|d| d.value) {
+            Some(biblatex::DateValue::At(d)) => format!(" ({})", d.year),
+            _ => String::new(),
+        };
+        text.push_str(&format!("\n\n{}{}", authors.join(", "), year));
+        let venue = ["journal", "journaltitle", "booktitle"]
+            .iter()
+            .find_map(|f| bib.get(f).map(|c| c.format_sentence()));
+        if let Some(v) = venue {
+            text.push_str(&format!("\n\n*{}*", v));
+        }
+    }
+    text
+}
+
+fn lines(text: &str) -> impl Iterator<Item = Vec<char>> + '_ {
+    text.split('\n')
+        .map(|l| l.trim_end_matches('\r').chars().collect())
+}
+
+fn regions(line: &[char]) -> Vec<Region> {
+    let mut regions = Vec::new();
+    let closing = |start: usize, c: char| {
+        line[start..]
+            .iter()
+            .position(|&x| x == c)
+            .map_or(line.len(), |k| start + k)
+    };
+    let mut i = 0;
+    while i < line.len() {
+        match line[i] {
+            // \cite, \citep, \parencite*, \textcite[p.~1]{a}, ...
+            '\\' => {
+                let mut j = i + 1;
+                while j < line.len() && line[j].is_ascii_alphabetic() {
+                    j += 1;
+                }
+                let command: String = line[i + 1..j].iter().collect();
+                if !command.to_lowercase().contains("cite") {
+                    i = j;
+                    continue;
+                }
+                if j < line.len() && line[j] == '*' {
+                    j += 1;
+                }
+                while j < line.len() && (line[j] == '[' || line[j] == ' ') {
+                    j = match line[j] {
+                        '[' => closing(j, ']') + 1,
+                        _ => j + 1,
+                    };
+                }
+                if j < line.len() && line[j] == '{' {
+                    let (start, end) = (j + 1, closing(j + 1, '}'));
+                    let keys = latex_keys(line, start, end);
+                    regions.push(Region { start, end, keys });
+                    i = end;
+                } else {
+                    i = j;
+                }
+            }
+            '[' => {
+                let (start, end) = (i + 1, closing(i + 1, ']'));
+                let keys = pandoc_keys(line, start, end);
+                if !keys.is_empty() {
+                    regions.push(Region { start, end, keys });
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    regions
+}
+
+// comma separated keys
+fn latex_keys(line: &[char], start: usize, end: usize) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut from = start;
+    for k in start..=end {
+        if k < end && line[k] != ',' {
+            continue;
+        }
+        let (mut a, mut b) = (from, k);
+        while a < b && line[a].is_whitespace() {
+            a += 1;
+        }
+        while b > a && line[b - 1].is_whitespace() {
+            b -= 1;
+        }
+        let name = line[a..b].iter().collect();
+        keys.push(Key {
+            start: a,
+            end: b,
+            name,
+        });
+        from = k + 1;
+    }
+    keys
+}
+
+// keys after `@`, e.g. `[see @smith2013, p. 4; -@doe2020]`
+fn pandoc_keys(line: &[char], start: usize, end: usize) -> Vec<Key> {
+    let mut keys = Vec::new();
+    for k in start..end {
+        let after_space = k == start || matches!(line[k - 1], ' ' | ';' | '-');
+        if line[k] != '@' || !after_space {
+            continue;
+        }
+        let a = k + 1;
+        let mut b = a;
+        while b < end && (line[b].is_alphanumeric() || "_:.#$%&-+?<>~/".contains(line[b])) {
+            b += 1;
+        }
+        // punctuation at the end is not part of key
+        while b > a && !(line[b - 1].is_alphanumeric() || line[b - 1] == '_') {
+            b -= 1;
+        }
+        let name = line[a..b].iter().collect();
+        keys.push(Key {
+            start: a,
+            end: b,
+            name,
+        });
+    }
+    keys
+}
+
+// positions of the protocol count UTF-16 code units
+fn to_utf16(line: &[char], col: usize) -> usize {
+    line[..col.min(line.len())]
+        .iter()
+        .map(|c| c.len_utf16())
+        .sum()
+}
+
+fn from_utf16(line: &[char], col: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.iter().enumerate() {
+        if units >= col {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+fn range(line: &[char], n: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": {"line": n, "character": to_utf16(line, start)},
+        "end": {"line": n, "character": to_utf16(line, end)},
+    })
+}
+
+fn file_uri(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    reqwest::Url::from_file_path(path)
+        .ok()
+        .map(|u| u.to_string())
+}
+
+fn publish(out: &mut impl Write, uri: &str, diagnostics: Vec<Value>) -> io::Result<()> {
+    let params = json!({"uri": uri, "diagnostics": diagnostics});
+    let msg =
+        json!({"jsonrpc": "2.0", "method": "textDocument/publishDiagnostics", "params": params});
+    write_message(out, &msg)
+}
+
+// messages are JSON with a `Content-Length` header
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>, Box<dyn Error>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(v) = header.strip_prefix("Content-Length:") {
+            length = Some(v.trim().parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; length.ok_or("message without Content-Length")?];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(out: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    fn keys(line: &str) -> Vec<String> {
+        let line: Vec<char> = line.chars().collect();
+        regions(&line)
+            .into_iter()
+            .flat_map(|r| r.keys)
+            .map(|k| k.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_regions() {
+        assert_eq!(
+            keys(r"as in \citep[p.~4]{swan, rl} and \textcite{ü}, \ref{fig}"),
+            vec!["swan", "rl", "ü"]
+        );
+        assert_eq!(
+            keys("see [@swan, p. 4; -@doe:2020.] or [link](x) mail a@b.c"),
+            vec!["swan", "doe:2020"]
+        );
+        // keys being typed are empty
+        assert_eq!(keys(r"\cite{swan,"), vec!["swan", ""]);
+        assert_eq!(keys("[@"), vec![""]);
+        let line: Vec<char> = "😀 [@a]".chars().collect();
+        assert_eq!(to_utf16(&line, 4), 5);
+        assert_eq!(from_utf16(&line, 5), 4);
+    }
+}
@@ -1,4 +1,8 @@
-use refhelper::{cli, Library};
+use refhelper::cli::ShellOptions;
+use refhelper::lint::LintOptions;
+use refhelper::normalize::GenOptions;
+use refhelper::{cli, config, lsp, server, tui, Library};
+use std::net::{IpAddr, SocketAddr};
 use std::{error::Error, path::PathBuf};
 use structopt::StructOpt;
 
@@ -7,7 +11,20 @@ enum SubCommand {
     /// Start interactive CLI
     #[structopt(name = "cli")]
     Cli {
-        /// path of library
+        /// path of library, default is `library` of config
+        #[structopt(parse(from_os_str))]
+        lib: Option<PathBuf>,
+        /// path of library, same as the positional one
+        #[structopt(long = "lib", parse(from_os_str), conflicts_with = "lib")]
+        lib_opt: Option<PathBuf>,
+        #[structopt(flatten)]
+        opts: ShellOptions,
+    },
+
+    /// Browse and edit library in a full-screen terminal UI
+    #[structopt(name = "tui")]
+    Tui {
+        /// path of library, default is `library` of config
         #[structopt(parse(from_os_str))]
         lib: Option<PathBuf>,
     },
@@ -15,9 +32,76 @@ enum SubCommand {
     /// Generate bibtex file from library
     #[structopt(name = "gen")]
     Gen {
-        /// path of library
+        /// path of library, default is `library` of config
+        #[structopt(parse(from_os_str))]
+        lib: Option<PathBuf>,
+        #[structopt(flatten)]
+        opts: GenOptions,
+    },
+
+    /// Check entries of library, exit with 1 if issues are left
+    #[structopt(name = "lint")]
+    Lint {
+        /// path of library, default is `library` of config
+        #[structopt(parse(from_os_str))]
+        lib: Option<PathBuf>,
+        #[structopt(flatten)]
+        opts: LintOptions,
+    },
+
+    /// Copy library to another format, e.g. `migrate refs.json refs.db`
+    #[structopt(name = "migrate")]
+    Migrate {
+        #[structopt(parse(from_os_str))]
+        from: PathBuf,
+        #[structopt(parse(from_os_str))]
+        to: PathBuf,
+    },
+
+    /// Show entries added, removed and changed from library `a` to `b`
+    #[structopt(name = "diff")]
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
         #[structopt(parse(from_os_str))]
-        lib: PathBuf,
+        b: PathBuf,
+    },
+
+    /// Merge two edits of a JSON library, as git merge driver `refhelper merge %O %A %B`
+    #[structopt(name = "merge")]
+    Merge {
+        /// common ancestor
+        #[structopt(parse(from_os_str))]
+        base: PathBuf,
+        /// our version, also the output if `-o` is not given
+        #[structopt(parse(from_os_str))]
+        ours: PathBuf,
+        /// their version
+        #[structopt(parse(from_os_str))]
+        theirs: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+
+    /// Serve library as a JSON REST API
+    #[structopt(name = "serve")]
+    Serve {
+        /// path of library, default is `library` of config
+        #[structopt(long, parse(from_os_str))]
+        lib: Option<PathBuf>,
+        #[structopt(long, default_value = "8080")]
+        port: u16,
+        /// address to listen on, other machines can connect with 0.0.0.0
+        #[structopt(long, default_value = "127.0.0.1")]
+        host: IpAddr,
+    },
+
+    /// Language server over stdio completing citekeys in LaTeX and Markdown
+    #[structopt(name = "lsp")]
+    Lsp {
+        /// path of library, default is `library` of config
+        #[structopt(long, parse(from_os_str))]
+        lib: Option<PathBuf>,
     },
 }
 
@@ -28,17 +112,53 @@ struct ArgCommand {
     sub: Option<SubCommand>,
 }
 
+// library given on command line, or the default one of config
+fn library(lib: Option<PathBuf>) -> Result<PathBuf, Box<dyn Error>> {
+    lib.or_else(|| config::get().library.clone())
+        .ok_or_else(|| "no library given, and no default `library` in config".into())
+}
+
 // #[tokio::main]
 fn main() -> Result<(), Box<dyn Error>> {
     let args = ArgCommand::from_args();
+    config::load()?;
     match args.sub {
-        Some(SubCommand::Cli { lib }) => {
-            cli::loop_run(lib)?;
+        Some(SubCommand::Cli { lib, lib_opt, opts }) => {
+            cli::loop_run(lib.or(lib_opt), &opts)?;
+        }
+        Some(SubCommand::Tui { lib }) => tui::run(Library::from_path(library(lib)?)?)?,
+        Some(SubCommand::Gen { lib, opts }) => {
+            Library::from_path(library(lib)?)?.gen_bibtex(None, &opts)?;
+        }
+        Some(SubCommand::Lint { lib, opts }) => {
+            let mut lib = Library::from_path(library(lib)?)?;
+            let unfixed = lib.lint(&opts);
+            if opts.fix {
+                lib.save()?;
+            }
+            if unfixed > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(SubCommand::Migrate { from, to }) => Library::migrate(&from, &to)?,
+        Some(SubCommand::Diff { a, b }) => Library::diff(&a, &b)?,
+        Some(SubCommand::Merge {
+            base,
+            ours,
+            theirs,
+            output,
+        }) => {
+            let out = output.unwrap_or_else(|| ours.clone());
+            if Library::merge(&base, &ours, &theirs, &out)? > 0 {
+                std::process::exit(1);
+            }
         }
-        Some(SubCommand::Gen { lib }) => {
-            Library::from_path(lib)?.gen_bibtex(None);
+        Some(SubCommand::Serve { lib, port, host }) => {
+            let lib = Library::from_path(library(lib)?)?;
+            server::serve(lib, SocketAddr::new(host, port))?;
         }
-        None => cli::loop_run(None)?,
+        Some(SubCommand::Lsp { lib }) => lsp::run(&library(lib)?)?,
+        None => cli::loop_run(None, &ShellOptions::default())?,
     }
     Ok(())
 }
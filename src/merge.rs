@@ -0,0 +1,238 @@
+// three-way merge of JSON libraries, per entry and per field,
+// entries are matched by their `uid` and fields changed on both sides are conflicts
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::Path;
+
+use crate::storage::{self, RawLibrary};
+use crate::Entry;
+
+pub struct Conflict {
+    pub name: String,
+    pub field: String,
+}
+
+// merge `ours` and `theirs` edits of `base` into `out`, as a git merge driver:
+// `refhelper merge %O %A %B` writes the result to %A
+pub fn merge_files(
+    base: &Path,
+    ours: &Path,
+    theirs: &Path,
+    out: &Path,
+) -> Result<Vec<Conflict>, Box<dyn Error>> {
+    let base = storage::read_json(base)?;
+    let ours = storage::read_json(ours)?;
+    let theirs = storage::read_json(theirs)?;
+    let (merged, conflicts) = merge(base, ours, theirs);
+    let entries = merged
+        .entries
+        .into_iter()
+        .map(serde_json::from_value)
+        .collect::<Result<Vec<Entry>, _>>()?;
+    storage::write_json(out, merged.version, &entries, &merged.extra)?;
+    Ok(conflicts)
+}
+
+pub fn merge(
+    base_lib: RawLibrary,
+    ours_lib: RawLibrary,
+    theirs_lib: RawLibrary,
+) -> (RawLibrary, Vec<Conflict>) {
+    let mut conflicts = Vec::new();
+    let base: HashMap<String, Value> = keyed(base_lib.entries).into_iter().collect();
+    let ours = keyed(ours_lib.entries);
+    let theirs = keyed(theirs_lib.entries);
+    let ours_map: HashMap<&String, &Value> = ours.iter().map(|(k, v)| (k, v)).collect();
+    let theirs_map: HashMap<&String, &Value> = theirs.iter().map(|(k, v)| (k, v)).collect();
+
+    // order of ours, then entries only added by theirs
+    let order = ours
+        .iter()
+        .chain(theirs.iter().filter(|(k, _)| !ours_map.contains_key(k)))
+        .map(|(k, _)| k);
+    let mut entries = Vec::new();
+    for key in order {
+        let b = base.get(key).and_then(|v| v.as_object());
+        let o = ours_map.get(key).and_then(|v| v.as_object());
+        let t = theirs_map.get(key).and_then(|v| v.as_object());
+        let entry = match (b, o, t) {
+            (_, Some(o), Some(t)) => {
+                let (mut merged, fields) = merge_fields(b, o, t);
+                if !fields.is_empty() {
+                    let mut recorded = Map::new();
+                    for field in fields {
+                        recorded.insert(
+                            field.clone(),
+                            json!({"ours": o.get(&field), "theirs": t.get(&field)}),
+                        );
+                        conflicts.push(Conflict {
+                            name: entry_name(o),
+                            field,
+                        });
+                    }
+                    merged.insert(String::from("conflicts"), Value::Object(recorded));
+                }
+                merged
+            }
+            // deleted on one side, kept if changed on the other one
+            (Some(b), Some(e), None) | (Some(b), None, Some(e)) => {
+                if e == b {
+                    continue;
+                }
+                let mut kept = e.clone();
+                let side = if o.is_some() { "theirs" } else { "ours" };
+                kept.insert(
+                    String::from("conflicts"),
+                    json!({"entry": format!("deleted in {}", side)}),
+                );
+                conflicts.push(Conflict {
+                    name: entry_name(e),
+                    field: String::from("entry"),
+                });
+                kept
+            }
+            (None, Some(e), None) | (None, None, Some(e)) => e.clone(),
+            (_, None, None) => continue,
+        };
+        entries.push(Value::Object(entry));
+    }
+
+    let (extra, fields) = merge_fields(Some(&base_lib.extra), &ours_lib.extra, &theirs_lib.extra);
+    for field in fields {
+        conflicts.push(Conflict {
+            name: String::from("library"),
+            field,
+        });
+    }
+    let merged = RawLibrary {
+        version: ours_lib.version.max(theirs_lib.version),
+        entries,
+        extra,
+    };
+    (merged, conflicts)
+}
+
+// key entries by uid, with a counter for copies of the same entry
+fn keyed(entries: Vec<Value>) -> Vec<(String, Value)> {
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    entries
+        .into_iter()
+        .map(|e| {
+            let uid = e
+                .get("uid")
+                .and_then(|u| u.as_str())
+                .unwrap_or("")
+                .to_owned();
+            let n = seen.entry(uid.clone()).or_insert(0);
+            *n += 1;
+            (format!("{}#{}", uid, n), e)
+        })
+        .collect()
+}
+
+fn entry_name(entry: &Map<String, Value>) -> String {
+    entry
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or("")
+        .to_owned()
+}
+
+// returns merged fields and fields changed differently on both sides
+fn merge_fields(
+    base: Option<&Map<String, Value>>,
+    ours: &Map<String, Value>,
+    theirs: &Map<String, Value>,
+) -> (Map<String, Value>, Vec<String>) {
+    let mut merged = Map::new();
+    let mut conflicts = Vec::new();
+    let keys: BTreeSet<&String> = ours
+        .keys()
+        .chain(theirs.keys())
+        .chain(base.iter().flat_map(|b| b.keys()))
+        .collect();
+    for key in keys {
+        let (b, o, t) = (
+            base.and_then(|b| b.get(key)),
+            ours.get(key),
+            theirs.get(key),
+        );
+        let value = if o == t || t == b {
+            o
+        } else if o == b {
+            t
+        } else {
+            conflicts.push(key.clone());
+            o
+        };
+        if let Some(v) = value {
+            merged.insert(key.clone(), v.clone());
+        }
+    }
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    fn lib(entries: Value) -> RawLibrary {
+        RawLibrary {
+            version: 3,
+            entries: entries.as_array().unwrap().clone(),
+            extra: Map::new(),
+        }
+    }
+
+    #[test]
+    fn test_merge() {
+        let base = lib(json!([
+            {"uid": "a", "name": "swan", "note": "", "title": "SWAN"},
+            {"uid": "b", "name": "b4", "note": ""},
+            {"uid": "c", "name": "old", "note": ""},
+        ]));
+        let ours = lib(json!([
+            {"uid": "a", "name": "swan", "note": "read", "title": "SWAN"},
+            {"uid": "b", "name": "b4", "note": "ours"},
+            {"uid": "d", "name": "new1", "note": ""},
+        ]));
+        let theirs = lib(json!([
+            {"uid": "a", "name": "swan", "note": "", "title": "SWAN!"},
+            {"uid": "b", "name": "b4", "note": "theirs"},
+            {"uid": "c", "name": "old", "note": ""},
+            {"uid": "e", "name": "new2", "note": ""},
+        ]));
+        let (merged, conflicts) = merge(base, ours, theirs);
+        let names: Vec<_> = merged
+            .entries
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["swan", "b4", "new1", "new2"]);
+        assert_eq!(merged.entries[0]["note"], "read");
+        assert_eq!(merged.entries[0]["title"], "SWAN!");
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(
+            (conflicts[0].name.as_str(), conflicts[0].field.as_str()),
+            ("b4", "note")
+        );
+        assert_eq!(merged.entries[1]["note"], "ours");
+        assert_eq!(merged.entries[1]["conflicts"]["note"]["theirs"], "theirs");
+    }
+
+    #[test]
+    fn test_merge_library_fields() {
+        let with = |fields: Value| RawLibrary {
+            extra: fields.as_object().unwrap().clone(),
+            ..lib(json!([]))
+        };
+        let base = with(json!({"name": "refs", "owner": "me"}));
+        let ours = with(json!({"name": "refs", "owner": "us"}));
+        let theirs = with(json!({"name": "papers", "owner": "me"}));
+        let (merged, conflicts) = merge(base, ours, theirs);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged.extra["name"], "papers");
+        assert_eq!(merged.extra["owner"], "us");
+    }
+}
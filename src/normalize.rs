@@ -0,0 +1,781 @@
+// normalize bibtex before output: field names and dates for bibtex or biblatex,
+// unicode to latex and back, brace protection of titles, page ranges, duplicated urls
+use biblatex::{Bibliography, Chunk, DateValue, Entry, EntryType};
+use std::borrow::Cow;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use structopt::StructOpt;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::abbrev::{AbbrevMode, Abbreviations};
+
+// fields whose content is written as is
+const RAW_FIELDS: &[&str] = &["url", "doi", "eprint", "file"];
+const TITLE_FIELDS: &[&str] = &["title", "subtitle", "shorttitle"];
+const VENUE_FIELDS: &[&str] = &["journal", "journaltitle", "booktitle"];
+const FIELD_ORDER: &[&str] = &[
+    "author",
+    "editor",
+    "title",
+    "subtitle",
+    "booktitle",
+    "journal",
+    "journaltitle",
+    "series",
+    "edition",
+    "volume",
+    "number",
+    "issue",
+    "pages",
+    "year",
+    "month",
+    "date",
+    "publisher",
+    "organization",
+    "institution",
+    "school",
+    "address",
+    "location",
+    "doi",
+    "eprint",
+    "archiveprefix",
+    "eprinttype",
+    "url",
+];
+const MONTH_MACROS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+// accent commands and their combining marks, e.g. \"u is u + U+0308
+const ACCENTS: &[(&str, char)] = &[
+    ("`", '\u{0300}'),
+    ("'", '\u{0301}'),
+    ("^", '\u{0302}'),
+    ("~", '\u{0303}'),
+    ("=", '\u{0304}'),
+    ("u", '\u{0306}'),
+    (".", '\u{0307}'),
+    ("\"", '\u{0308}'),
+    ("r", '\u{030a}'),
+    ("H", '\u{030b}'),
+    ("v", '\u{030c}'),
+    ("c", '\u{0327}'),
+    ("k", '\u{0328}'),
+];
+// letters written as commands, e.g. {\ss}
+const LETTERS: &[(char, &str)] = &[
+    ('ß', "ss"),
+    ('æ', "ae"),
+    ('Æ', "AE"),
+    ('œ', "oe"),
+    ('Œ', "OE"),
+    ('ø', "o"),
+    ('Ø', "O"),
+    ('ł', "l"),
+    ('Ł', "L"),
+    ('ı', "i"),
+    ('å', "aa"),
+    ('Å', "AA"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Bibtex,
+    Biblatex,
+}
+
+impl Mode {
+    pub const NAMES: &'static [&'static str] = &["bibtex", "biblatex"];
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bibtex" => Ok(Mode::Bibtex),
+            "biblatex" => Ok(Mode::Biblatex),
+            _ => Err(format!("unknown mode '{}', expect bibtex or biblatex", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Protect {
+    None,
+    Acronyms,    // words with more than one capital letter, e.g. WAN, IPv6
+    Capitalized, // also every word starting with a capital letter
+}
+
+impl Protect {
+    pub const NAMES: &'static [&'static str] = &["none", "acronyms", "capitalized"];
+    // with aliases
+    pub const VALUES: &'static [&'static str] = &["none", "acronyms", "capitalized", "all"];
+}
+
+impl FromStr for Protect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Protect::None),
+            "acronyms" => Ok(Protect::Acronyms),
+            "capitalized" | "all" => Ok(Protect::Capitalized),
+            _ => Err(format!(
+                "unknown protection '{}', expect none, acronyms or capitalized",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub mode: Mode,
+    pub latex: bool, // convert unicode characters to latex commands
+    pub protect: Protect,
+    pub include: Vec<String>, // only keep these fields if not empty
+    pub exclude: Vec<String>,
+    pub venues: Option<Abbreviations>, // abbreviate or expand venue names
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            mode: Mode::Bibtex,
+            latex: true,
+            protect: Protect::Acronyms,
+            include: vec![],
+            exclude: vec![],
+            venues: None,
+        }
+    }
+}
+
+// options of `gen` shared by interactive CLI and command line,
+// bibtex is printed as stored unless one of them is given
+#[derive(StructOpt, Debug)]
+pub struct GenOptions {
+    /// Normalize bibtex, implied by the options below
+    #[structopt(long)]
+    pub normalize: bool,
+    /// Output dialect (bibtex, biblatex), bibtex by default
+    #[structopt(long, possible_values = Mode::NAMES, case_insensitive = true)]
+    pub mode: Option<Mode>,
+    /// Convert latex commands to unicode characters instead of the reverse
+    #[structopt(long)]
+    pub utf8: bool,
+    /// Brace protection of title words (none, acronyms, capitalized), acronyms by default
+    #[structopt(long, possible_values = Protect::VALUES, case_insensitive = true)]
+    pub protect: Option<Protect>,
+    /// Only output these fields, e.g. `--fields author,title,year`
+    #[structopt(long, use_delimiter = true)]
+    pub fields: Vec<String>,
+    /// Drop these fields, e.g. `--exclude abstract,file`
+    #[structopt(long, use_delimiter = true)]
+    pub exclude: Vec<String>,
+    /// Abbreviate journal and booktitle (iso4, custom)
+    #[structopt(long, possible_values = AbbrevMode::NAMES, case_insensitive = true)]
+    pub abbrev: Option<AbbrevMode>,
+    /// Expand abbreviated journal and booktitle to full names
+    #[structopt(long, conflicts_with = "abbrev")]
+    pub expand: bool,
+    /// File of `Full Name = Abbr.` lines, default is <library>.abbrev.txt
+    #[structopt(long, parse(from_os_str))]
+    pub abbrev_file: Option<PathBuf>,
+}
+
+impl GenOptions {
+    // None for stored bibtex
+    pub fn profile(&self, lib_path: &Path) -> Result<Option<Profile>, Box<dyn Error>> {
+        let normalize = self.normalize
+            || self.mode.is_some()
+            || self.utf8
+            || self.protect.is_some()
+            || !self.fields.is_empty()
+            || !self.exclude.is_empty()
+            || self.abbrev.is_some()
+            || self.expand;
+        if !normalize {
+            return Ok(None);
+        }
+        // mapping next to library is optional, e.g. refs.json -> refs.abbrev.txt
+        let default = lib_path.with_extension("abbrev.txt");
+        let mapping = match &self.abbrev_file {
+            Some(p) => Some(p.as_path()),
+            None if default.exists() => Some(default.as_path()),
+            None => None,
+        };
+        let venues = match (self.abbrev, self.expand) {
+            (Some(AbbrevMode::Custom), _) if mapping.is_none() => {
+                return Err(format!("abbreviation file {} not found", default.display()).into())
+            }
+            (Some(mode), _) => Some(Abbreviations::load(mode, mapping)?),
+            (None, true) => Some(Abbreviations::expansions(mapping)?),
+            (None, false) => None,
+        };
+        let default = Profile::default();
+        Ok(Some(Profile {
+            mode: self.mode.unwrap_or(default.mode),
+            latex: !self.utf8,
+            protect: self.protect.unwrap_or(default.protect),
+            include: self.fields.iter().map(|f| f.to_lowercase()).collect(),
+            exclude: self.exclude.iter().map(|f| f.to_lowercase()).collect(),
+            venues,
+        }))
+    }
+}
+
+// stored bibtex of an entry in the form of profile, None if it does not parse
+pub fn normalize_bibtex(bibtex: &str, profile: &Profile) -> Option<String> {
+    // biblatex drops some commands, e.g. {\v S} -> S, so they are resolved first
+    let bibs = Bibliography::parse(&from_latex(bibtex))?;
+    let entry = bibs.iter().next()?;
+    Some(normalize(entry, profile))
+}
+
+pub fn normalize(entry: &Entry, profile: &Profile) -> String {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut push = |k: &str, v: String| fields.push((String::from(k), v));
+
+    let thesis = matches!(
+        entry.entry_type,
+        EntryType::PhdThesis | EntryType::MastersThesis | EntryType::Thesis
+    );
+    let doi = entry.get("doi").map(|c| clean_doi(&raw_text(c)));
+    let date = entry.date().and_then(|d| match d.value {
+        DateValue::At(d) => Some(d),
+        _ => None,
+    });
+    for (key, chunks) in entry.fields.iter() {
+        let rewritten;
+        let chunks = match &profile.venues {
+            Some(v) if VENUE_FIELDS.contains(&key.as_str()) => {
+                rewritten = rewrite_venue(chunks, v);
+                &rewritten
+            }
+            _ => chunks,
+        };
+        let key = match (profile.mode, key.as_str()) {
+            (Mode::Bibtex, "journaltitle") => "journal",
+            (Mode::Bibtex, "location") => "address",
+            (Mode::Bibtex, "institution") if thesis => "school",
+            (Mode::Biblatex, "journal") => "journaltitle",
+            (Mode::Biblatex, "address") => "location",
+            (Mode::Biblatex, "school") => "institution",
+            (_, k) => k,
+        };
+        match key {
+            "date" | "year" | "month" | "day" if date.is_some() => continue,
+            "doi" => push(key, doi.clone().unwrap_or_default()),
+            "url" if is_doi_url(&raw_text(chunks), doi.as_deref()) => continue,
+            "pages" => push(
+                key,
+                page_range(&to_latex(chunks, profile.latex, Protect::None)),
+            ),
+            k if RAW_FIELDS.contains(&k) => push(k, raw_text(chunks)),
+            k if TITLE_FIELDS.contains(&k) => {
+                push(k, to_latex(chunks, profile.latex, profile.protect))
+            }
+            k => push(k, to_latex(chunks, profile.latex, Protect::None)),
+        }
+    }
+    if let Some(d) = date {
+        match profile.mode {
+            Mode::Bibtex => {
+                push("year", d.year.to_string());
+                if let Some(m) = d.month {
+                    // month macros are written without braces
+                    push("month", format!("#{}", MONTH_MACROS[m as usize]));
+                }
+            }
+            Mode::Biblatex => {
+                let mut value = format!("{:04}", d.year);
+                if let Some(m) = d.month {
+                    value += &format!("-{:02}", m + 1);
+                    if let Some(day) = d.day {
+                        value += &format!("-{:02}", day + 1);
+                    }
+                }
+                push("date", value);
+            }
+        }
+    }
+
+    fields.retain(|(k, _)| {
+        (profile.include.is_empty() || profile.include.contains(k)) && !profile.exclude.contains(k)
+    });
+    sort_fields(&mut fields);
+
+    let entry_type = match profile.mode {
+        Mode::Bibtex => entry.entry_type.to_bibtex(),
+        Mode::Biblatex => entry.entry_type.to_biblatex(),
+    };
+    let entry_type = match entry_type {
+        EntryType::Unknown(t) => t,
+        t => t.to_string(),
+    };
+    let mut out = format!("@{}{{{},\n", entry_type, entry.key);
+    for (k, v) in fields {
+        match v.strip_prefix('#') {
+            Some(m) if k == "month" => out += &format!("  {} = {},\n", k, m),
+            _ => out += &format!("  {} = {{{}}},\n", k, v),
+        }
+    }
+    out.push('}');
+    out
+}
+
+fn raw_text(chunks: &[Chunk]) -> String {
+    chunks
+        .iter()
+        .map(|c| match c {
+            Chunk::Normal(s) | Chunk::Verbatim(s) => s.as_str(),
+        })
+        .collect()
+}
+
+// venue names are rewritten around {…}-protected parts, which are kept as they are
+fn rewrite_venue(chunks: &[Chunk], venues: &Abbreviations) -> Vec<Chunk> {
+    if let Some(name) = venues.lookup(&raw_text(chunks)) {
+        return braced_chunks(&name);
+    }
+    // protected parts are numbered placeholders while words are abbreviated,
+    // which are never changed since they have digits
+    let mut protected = Vec::new();
+    let mut name = String::new();
+    for chunk in chunks {
+        match chunk {
+            Chunk::Normal(s) => name += s,
+            Chunk::Verbatim(s) => {
+                name += &format!("\u{1}{}\u{1}", protected.len());
+                protected.push(s);
+            }
+        }
+    }
+    venues
+        .rewrite(&name)
+        .split('\u{1}')
+        .enumerate()
+        .filter(|(_, s)| !s.is_empty())
+        .map(
+            |(i, s)| match s.parse::<usize>().ok().and_then(|n| protected.get(n)) {
+                Some(p) if i % 2 == 1 => Chunk::Verbatim(p.to_string()),
+                _ => Chunk::Normal(String::from(s)),
+            },
+        )
+        .collect()
+}
+
+// names in mapping files may protect parts too, e.g. "Proc. {USENIX} ATC"
+fn braced_chunks(s: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('{') {
+        let end = match rest[start..].find('}') {
+            Some(len) => start + len,
+            None => break,
+        };
+        if start > 0 {
+            chunks.push(Chunk::Normal(String::from(&rest[..start])));
+        }
+        chunks.push(Chunk::Verbatim(String::from(&rest[start + 1..end])));
+        rest = &rest[end + 1..];
+    }
+    if !rest.is_empty() {
+        chunks.push(Chunk::Normal(String::from(rest)));
+    }
+    chunks
+}
+
+// conventional order of fields, unknown ones last in alphabetic order
+pub fn sort_fields<T>(fields: &mut [(String, T)]) {
+    fields.sort_by_key(|(k, _)| {
+        let pos = FIELD_ORDER.iter().position(|f| f == k);
+        (pos.unwrap_or(FIELD_ORDER.len()), k.clone())
+    });
+}
+
+pub fn clean_doi(doi: &str) -> String {
+    let doi = doi.trim();
+    for prefix in [
+        "https://doi.org/",
+        "http://doi.org/",
+        "https://dx.doi.org/",
+        "http://dx.doi.org/",
+        "doi:",
+    ] {
+        if doi.len() > prefix.len() && doi[..prefix.len()].eq_ignore_ascii_case(prefix) {
+            return String::from(&doi[prefix.len()..]);
+        }
+    }
+    String::from(doi)
+}
+
+fn is_doi_url(url: &str, doi: Option<&str>) -> bool {
+    match doi {
+        Some(doi) if !doi.is_empty() => {
+            let url = url.trim().to_lowercase();
+            (url.contains("doi.org/") || url.starts_with("doi:"))
+                && clean_doi(&url) == doi.to_lowercase()
+        }
+        _ => false,
+    }
+}
+
+// "15-26", "15 – 26" -> "15--26"
+fn page_range(pages: &str) -> String {
+    pages
+        .split(',')
+        .map(|range| {
+            let parts: Vec<&str> = range
+                .split(['-', '\u{2013}', '\u{2014}'])
+                .map(|p| p.trim())
+                .filter(|p| !p.is_empty())
+                .collect();
+            match parts.len() {
+                2 => format!("{}--{}", parts[0], parts[1]),
+                _ => String::from(range.trim()),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn to_latex(chunks: &[Chunk], latex: bool, protect: Protect) -> String {
+    let mut out = String::new();
+    for chunk in chunks {
+        match chunk {
+            // a braced accent like {\"u} is parsed as a verbatim "ü"
+            Chunk::Verbatim(s) if s.chars().count() == 1 && !s.is_ascii() => {
+                out += &escape(s, latex);
+            }
+            Chunk::Verbatim(s) => {
+                out += &format!("{{{}}}", escape(s, latex));
+            }
+            Chunk::Normal(s) => {
+                for (text, math) in math_spans(s) {
+                    if math {
+                        out += text;
+                        continue;
+                    }
+                    let text = match latex {
+                        true => Cow::Borrowed(text),
+                        false => Cow::Owned(unicode_punctuation(text)),
+                    };
+                    let mut word = String::new();
+                    for c in text.chars() {
+                        if c.is_alphanumeric() {
+                            word.push(c);
+                            continue;
+                        }
+                        out += &protect_word(&word, latex, protect);
+                        word.clear();
+                        out += &escape(&c.to_string(), latex);
+                    }
+                    out += &protect_word(&word, latex, protect);
+                }
+            }
+        }
+    }
+    out
+}
+
+// split text into parts and whether they are math, i.e. "$...$" with a closing "$"
+fn math_spans(s: &str) -> Vec<(&str, bool)> {
+    // a "$" which is not escaped
+    let dollar = |s: &str| {
+        s.char_indices()
+            .find(|&(i, c)| c == '$' && !s[..i].ends_with('\\'))
+            .map(|(i, _)| i)
+    };
+    let mut spans = Vec::new();
+    let mut rest = s;
+    while let Some(start) = dollar(rest) {
+        let end = match dollar(&rest[start + 1..]) {
+            Some(len) => start + len + 2,
+            None => break,
+        };
+        spans.push((&rest[..start], false));
+        spans.push((&rest[start..end], true));
+        rest = &rest[end..];
+    }
+    spans.push((rest, false));
+    spans
+}
+
+// "--" -> "–", "``quote''" -> "“quote”"
+fn unicode_punctuation(s: &str) -> String {
+    s.replace("---", "\u{2014}")
+        .replace("--", "\u{2013}")
+        .replace("``", "\u{201c}")
+        .replace("''", "\u{201d}")
+}
+
+// latex commands of accents and letters to unicode, e.g. M\"uller {\v S} -> Müller {Š},
+// other commands and math are kept
+pub fn from_latex(s: &str) -> String {
+    let mut out = String::new();
+    for (text, math) in math_spans(s) {
+        if math {
+            out += text;
+            continue;
+        }
+        let mut rest = text;
+        while let Some(i) = rest.find('\\') {
+            out += &rest[..i];
+            rest = &rest[i + 1..];
+            match command(rest) {
+                Some((c, len)) => {
+                    out += &c;
+                    rest = &rest[len..];
+                }
+                // "\\" is a backslash itself
+                None if rest.starts_with('\\') => {
+                    out += "\\\\";
+                    rest = &rest[1..];
+                }
+                None => out.push('\\'),
+            }
+        }
+        out += rest;
+    }
+    out
+}
+
+// unicode of a command at the start of s (after the backslash) and its length
+fn command(s: &str) -> Option<(String, usize)> {
+    let letters = s
+        .find(|c: char| !c.is_ascii_alphabetic())
+        .unwrap_or(s.len());
+    let len = match letters {
+        0 => s.chars().next()?.len_utf8(),
+        n => n,
+    };
+    let name = &s[..len];
+    if let Some(&(_, mark)) = ACCENTS.iter().find(|(cmd, _)| *cmd == name) {
+        // argument is a letter, {x} or \i, after spaces if the command is a word
+        let arg = match letters {
+            0 => &s[len..],
+            _ => s[len..].trim_start(),
+        };
+        let (base, arg_len) = match arg.strip_prefix('{') {
+            Some(inner) => {
+                let end = inner.find('}')?;
+                (base_letter(&inner[..end])?, end + 2)
+            }
+            None => {
+                let end = match arg.starts_with('\\') {
+                    true => 2,
+                    false => arg.chars().next()?.len_utf8(),
+                };
+                (base_letter(arg.get(..end)?)?, end)
+            }
+        };
+        let composed = format!("{}{}", base, mark).nfc().collect();
+        return Some((composed, s.len() - arg.len() + arg_len));
+    }
+    let c = match name {
+        "ldots" | "dots" => '\u{2026}',
+        "textendash" => '\u{2013}',
+        "textemdash" => '\u{2014}',
+        n => LETTERS.iter().find(|(_, cmd)| *cmd == n)?.0,
+    };
+    // a space or {} ends the name of a command
+    let rest = &s[len..];
+    let len = match rest {
+        r if r.starts_with("{}") => len + 2,
+        r if r.starts_with(' ') => len + 1,
+        _ => len,
+    };
+    Some((c.to_string(), len))
+}
+
+fn base_letter(arg: &str) -> Option<char> {
+    match arg.trim() {
+        "\\i" => Some('i'),
+        "\\j" => Some('j'),
+        a if a.chars().count() == 1 => a.chars().next().filter(|c| c.is_alphabetic()),
+        _ => None,
+    }
+}
+
+fn protect_word(word: &str, latex: bool, protect: Protect) -> String {
+    let capitals = word.chars().filter(|c| c.is_uppercase()).count();
+    let later_capital = word.chars().skip(1).any(|c| c.is_uppercase());
+    let first_capital = word.chars().next().is_some_and(|c| c.is_uppercase());
+    let protected = match protect {
+        Protect::None => false,
+        Protect::Acronyms => capitals > 1 || later_capital,
+        Protect::Capitalized => first_capital || later_capital,
+    };
+    let word = escape(word, latex);
+    if protected {
+        format!("{{{}}}", word)
+    } else {
+        word
+    }
+}
+
+fn escape(s: &str, latex: bool) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        match c {
+            '&' | '%' | '$' | '#' | '_' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\\' => out += "\\textbackslash{}",
+            c if c.is_ascii() || !latex => out.push(c),
+            c => out += &latex_char(c),
+        }
+    }
+    out
+}
+
+fn latex_char(c: char) -> String {
+    if let Some((_, cmd)) = LETTERS.iter().find(|(l, _)| *l == c) {
+        return format!("{{\\{}}}", cmd);
+    }
+    let special = match c {
+        '\u{2013}' => Some("--"),
+        '\u{2014}' => Some("---"),
+        '\u{2018}' => Some("`"),
+        '\u{2019}' => Some("'"),
+        '\u{201c}' => Some("``"),
+        '\u{201d}' => Some("''"),
+        '\u{2026}' => Some("\\ldots{}"),
+        '\u{00a0}' => Some("~"),
+        _ => None,
+    };
+    if let Some(s) = special {
+        return String::from(s);
+    }
+    // letters with accents, e.g. "ü" -> "{\"u}"
+    let mut chars = c.to_string().nfd().collect::<Vec<_>>().into_iter();
+    let base = match chars.next() {
+        Some(b) if b.is_ascii_alphabetic() => b,
+        _ => return c.to_string(),
+    };
+    let mut result = match base {
+        'i' => String::from("\\i"),
+        'j' => String::from("\\j"),
+        b => b.to_string(),
+    };
+    for mark in chars {
+        let cmd = match ACCENTS.iter().find(|(_, m)| *m == mark) {
+            Some((cmd, _)) => cmd,
+            None => return c.to_string(),
+        };
+        result = if cmd.chars().all(|c| c.is_ascii_alphabetic()) {
+            format!("\\{} {}", cmd, result)
+        } else {
+            format!("\\{}{}", cmd, result)
+        };
+    }
+    format!("{{{}}}", result)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use biblatex::Bibliography;
+
+    #[test]
+    fn test_normalize() {
+        let bib = Bibliography::parse(
+            "@article{k, title = {Achieving high utilization with software-driven WAN and IPv6}, \
+             author = {M{\\\"u}ller, Hans and Ła, Zo}, journaltitle = {Networks & Systems}, \
+             pages = {15 - 26}, date = {2013-06}, doi = {https://doi.org/10.1/ABC}, \
+             url = {https://doi.org/10.1/abc}, note = {x_y}}",
+        )
+        .unwrap();
+        let e = bib.iter().next().unwrap();
+        let bibtex = normalize(e, &Profile::default());
+        assert_eq!(
+            bibtex,
+            "@article{k,\n  \
+             author = {M{\\\"u}ller, Hans and {\\L}a, Zo},\n  \
+             title = {Achieving high utilization with software-driven {WAN} and {IPv6}},\n  \
+             journal = {Networks \\& Systems},\n  \
+             pages = {15--26},\n  \
+             year = {2013},\n  \
+             month = jun,\n  \
+             doi = {10.1/ABC},\n  \
+             note = {x\\_y},\n}"
+        );
+        let profile = Profile {
+            mode: Mode::Biblatex,
+            latex: false,
+            protect: Protect::None,
+            include: vec![],
+            exclude: vec![String::from("note"), String::from("author")],
+            venues: None,
+        };
+        let biblatex = normalize(e, &profile);
+        assert_eq!(
+            biblatex,
+            "@article{k,\n  \
+             title = {Achieving high utilization with software-driven WAN and IPv6},\n  \
+             journaltitle = {Networks \\& Systems},\n  \
+             pages = {15--26},\n  \
+             date = {2013-06},\n  \
+             doi = {10.1/ABC},\n}"
+        );
+    }
+
+    #[test]
+    fn test_latex() {
+        assert_eq!(
+            from_latex(r#"M\"uller {\v S}ediv\'{y} \ss{}x \"\i \emph{$\"a$} 5\%"#),
+            r#"Müller {Š}edivý ßx ï \emph{$\"a$} 5\%"#
+        );
+        let bib = r#"@article{k, title = {{\v S}ediv\'y: $O(n^2)$ sorting -- ``fast'' for 5\$}}"#;
+        let mut profile = Profile::default();
+        assert_eq!(
+            normalize_bibtex(bib, &profile).unwrap(),
+            "@article{k,\n  title = {{\\v S}ediv{\\'y}: $O(n^2)$ sorting -- ``fast'' for 5\\$},\n}"
+        );
+        profile.latex = false;
+        assert_eq!(
+            normalize_bibtex(bib, &profile).unwrap(),
+            "@article{k,\n  title = {Šedivý: $O(n^2)$ sorting – “fast” for 5\\$},\n}"
+        );
+    }
+
+    #[test]
+    fn test_venues() {
+        let bib = "@article{k, journal = {{IEEE} Journal of {Solid-State Circuits}}, \
+                   booktitle = {Proceedings of the {ACM} Symposium on Cloud Computing}}";
+        let profile = Profile {
+            venues: Some(Abbreviations::load(AbbrevMode::Iso4, None).unwrap()),
+            ..Profile::default()
+        };
+        assert_eq!(
+            normalize_bibtex(bib, &profile).unwrap(),
+            "@article{k,\n  \
+             booktitle = {Proc. {ACM} Symp. Cloud Comput.},\n  \
+             journal = {{IEEE} J. {Solid-State Circuits}},\n}"
+        );
+        assert_eq!(
+            braced_chunks("Proc. {USENIX} ATC"),
+            vec![
+                Chunk::Normal(String::from("Proc. ")),
+                Chunk::Verbatim(String::from("USENIX")),
+                Chunk::Normal(String::from(" ATC")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_gen_options() -> Result<(), Box<dyn Error>> {
+        let profile = |args: &[&str]| GenOptions::from_iter(args).profile(Path::new("refs.json"));
+        assert!(profile(&["gen"])?.is_none());
+        assert!(profile(&["gen", "--normalize"])?.is_some());
+        let utf8 = profile(&["gen", "--utf8"])?.unwrap();
+        assert!(!utf8.latex);
+        assert_eq!(utf8.protect, Protect::Acronyms);
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+// entries as flat records, printed as a table with chosen columns
+// or for scripts as JSON, JSON lines, CSV or TSV
+use biblatex::ChunksExt;
+use comfy_table::{ContentArrangement, Table};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+use crate::config;
+use crate::utils;
+use crate::Entry;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Output {
+    #[default]
+    Table,
+    Json,
+    Jsonl,
+    Csv,
+    Tsv,
+}
+
+impl Output {
+    pub const NAMES: &'static [&'static str] = &["table", "json", "jsonl", "csv", "tsv"];
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(Output::Table),
+            "json" => Ok(Output::Json),
+            "jsonl" => Ok(Output::Jsonl),
+            "csv" => Ok(Output::Csv),
+            "tsv" => Ok(Output::Tsv),
+            _ => Err(format!(
+                "unknown output '{}', expect table, json, jsonl, csv or tsv",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub enum Column {
+    Id,
+    Name,
+    Title,
+    Author,
+    Year,
+    Venue,
+    Doi,
+    Arxiv,
+    Url,
+    Tags,
+    // number of attachments, marked by `!` if some are missing
+    Path,
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "id" => Ok(Column::Id),
+            "name" | "citekey" => Ok(Column::Name),
+            "title" => Ok(Column::Title),
+            "author" | "authors" => Ok(Column::Author),
+            "year" => Ok(Column::Year),
+            "venue" => Ok(Column::Venue),
+            "doi" => Ok(Column::Doi),
+            "arxiv" => Ok(Column::Arxiv),
+            "url" => Ok(Column::Url),
+            "tags" | "tag" => Ok(Column::Tags),
+            "path" | "attachments" => Ok(Column::Path),
+            _ => Err(format!(
+                "unknown column '{}', expect id, name, title, author, year, venue, doi, arxiv, url, tags or path",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Column {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Column {
+    fn header(&self) -> &'static str {
+        match self {
+            Column::Id => "id",
+            Column::Name => "name",
+            Column::Title => "title",
+            Column::Author => "author",
+            Column::Year => "year",
+            Column::Venue => "venue",
+            Column::Doi => "doi",
+            Column::Arxiv => "arxiv",
+            Column::Url => "url",
+            Column::Tags => "tags",
+            Column::Path => "path",
+        }
+    }
+}
+
+const DEFAULT_COLUMNS: &[Column] = &[
+    Column::Id,
+    Column::Name,
+    Column::Title,
+    Column::Doi,
+    Column::Path,
+];
+
+// `year:desc`, ascending if not given
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey {
+    column: Column,
+    desc: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, order) = s.split_once(':').unwrap_or((s, "asc"));
+        let desc = match order {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(format!("unknown order '{}', expect asc or desc", order)),
+        };
+        Ok(SortKey {
+            column: column.parse()?,
+            desc,
+        })
+    }
+}
+
+// options of `list` and `search`
+#[derive(StructOpt, Debug, Default)]
+pub struct ListOptions {
+    /// Output format (table, json, jsonl, csv, tsv), default is `--output` of cli
+    #[structopt(long, possible_values = Output::NAMES)]
+    pub output: Option<Output>,
+    /// Columns of table, e.g. `author,year,venue,title,tags`, default is `columns` of config
+    #[structopt(long, use_delimiter = true)]
+    pub columns: Vec<Column>,
+    /// Sort by some columns, e.g. `year:desc,author`, search results are ranked otherwise
+    #[structopt(long, use_delimiter = true)]
+    pub sort: Vec<SortKey>,
+    /// Print at most this many entries
+    #[structopt(long)]
+    pub limit: Option<usize>,
+    /// Skip this many entries first
+    #[structopt(long, default_value = "0")]
+    pub offset: usize,
+}
+
+impl ListOptions {
+    pub fn output(&self) -> Output {
+        self.output.unwrap_or_default()
+    }
+
+    fn columns(&self) -> &[Column] {
+        match (&self.columns[..], &config::get().columns) {
+            ([], Some(columns)) if !columns.is_empty() => columns,
+            ([], _) => DEFAULT_COLUMNS,
+            (columns, _) => columns,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Identifiers {
+    pub doi: Option<String>,
+    pub arxiv: Option<String>,
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Record {
+    pub id: usize,
+    pub citekey: String,
+    pub title: String,
+    // "Family, Given"
+    pub authors: Vec<String>,
+    pub year: Option<i32>,
+    pub venue: Option<String>,
+    pub identifiers: Identifiers,
+    pub tags: Vec<String>,
+    // file paths, or urls
+    pub attachments: Vec<String>,
+    // attached files not found
+    #[serde(skip)]
+    pub broken: usize,
+}
+
+impl Record {
+    pub fn new(id: usize, entry: &Entry) -> Record {
+        let mut record = Record {
+            id,
+            citekey: entry.name.clone(),
+            title: entry.title.clone(),
+            authors: vec![],
+            year: None,
+            venue: None,
+            identifiers: Identifiers::default(),
+            tags: entry.tags(),
+            attachments: entry.attachments.iter().map(|a| a.location()).collect(),
+            broken: entry.attachments.iter().filter(|a| a.is_broken()).count(),
+        };
+        // the id an entry is added with is a DOI or an arXiv id
+        match &entry.doi {
+            id if id.starts_with("10.") => record.identifiers.doi = Some(id.clone()),
+            id if !id.is_empty() => record.identifiers.arxiv = Some(id.clone()),
+            _ => {}
+        }
+        let bib = match entry.parsed() {
+            Some(b) => b,
+            None => return record,
+        };
+        let field = |f: &str| bib.get(f).map(|c| c.format_verbatim());
+        // the title as written in BibTeX, `entry.title` is sentence-cased
+        if let Some(title) = bib.title() {
+            record.title = title.format_verbatim();
+        }
+        record.authors = bib
+            .author()
+            .unwrap_or_default()
+            .iter()
+            .map(|p| {
+                let family = format!("{} {}", p.prefix, p.name).trim().to_owned();
+                match p.given_name.as_str() {
+                    "" => family,
+                    given => format!("{}, {}", family, given),
+                }
+            })
+            .collect();
+        record.year = match bib.date().map(|d| d.value) {
+            Some(biblatex::DateValue::At(d)) => Some(d.year),
+            _ => None,
+        };
+        record.venue = ["journal", "journaltitle", "booktitle"]
+            .iter()
+            .find_map(|f| field(f));
+        let ids = &mut record.identifiers;
+        ids.doi = ids.doi.take().or_else(|| field("doi"));
+        ids.arxiv = ids.arxiv.take().or_else(|| field("eprint"));
+        ids.url = field("url");
+        record
+    }
+
+    // row of CSV and TSV
+    fn columns(&self) -> Vec<String> {
+        use Column::*;
+        let mut cells: Vec<String> = [Id, Name, Title, Author, Year, Venue, Doi, Arxiv, Url]
+            .iter()
+            .map(|&c| self.cell(c))
+            .collect();
+        cells.push(self.tags.join("; "));
+        cells.push(self.attachments.join("; "));
+        cells
+    }
+
+    fn cell(&self, column: Column) -> String {
+        let ids = &self.identifiers;
+        match column {
+            Column::Id => self.id.to_string(),
+            Column::Name => self.citekey.clone(),
+            Column::Title => self.title.clone(),
+            Column::Author => self.authors.join("; "),
+            Column::Year => self.year.map_or(String::new(), |y| y.to_string()),
+            Column::Venue => self.venue.clone().unwrap_or_default(),
+            Column::Doi => ids.doi.clone().unwrap_or_default(),
+            Column::Arxiv => ids.arxiv.clone().unwrap_or_default(),
+            Column::Url => ids.url.clone().unwrap_or_default(),
+            Column::Tags => self.tags.join(", "),
+            Column::Path => match self.broken {
+                0 => self.attachments.len().to_string(),
+                _ => format!("{}!", self.attachments.len()),
+            },
+        }
+    }
+
+    fn compare(&self, other: &Record, key: &SortKey) -> Ordering {
+        let order = match key.column {
+            Column::Id => self.id.cmp(&other.id),
+            Column::Year => self.year.cmp(&other.year),
+            c => {
+                let (a, b) = (self.cell(c), other.cell(c));
+                a.to_lowercase().cmp(&b.to_lowercase())
+            }
+        };
+        if key.desc {
+            order.reverse()
+        } else {
+            order
+        }
+    }
+}
+
+// header of CSV and TSV, lists are joined by "; "
+const COLUMNS: &[&str] = &[
+    "id",
+    "citekey",
+    "title",
+    "authors",
+    "year",
+    "venue",
+    "doi",
+    "arxiv",
+    "url",
+    "tags",
+    "attachments",
+];
+
+// sorted and paged by `opts`, in the order given otherwise
+pub fn print_entries(entries: &mut dyn Iterator<Item = (usize, &Entry)>, opts: &ListOptions) {
+    let mut records: Vec<Record> = entries.map(|(id, e)| Record::new(id, e)).collect();
+    records.sort_by(|a, b| {
+        opts.sort
+            .iter()
+            .map(|key| a.compare(b, key))
+            .find(|o| o.is_ne())
+            .unwrap_or(Ordering::Equal)
+    });
+    let records = records
+        .into_iter()
+        .skip(opts.offset)
+        .take(opts.limit.unwrap_or(usize::MAX));
+    match opts.output() {
+        Output::Table => utils::page(&format!("{}\n", table(records, opts.columns()))),
+        output => format_records(records, output)
+            .iter()
+            .for_each(|line| println!("{}", line)),
+    }
+}
+
+fn table(records: impl Iterator<Item = Record>, columns: &[Column]) -> Table {
+    let mut table = Table::new();
+    table.set_content_arrangement(ContentArrangement::Dynamic);
+    table.set_header(columns.iter().map(|c| c.header()));
+    for record in records {
+        table.add_row(columns.iter().map(|&c| record.cell(c)));
+    }
+    table
+}
+
+fn format_records(records: impl Iterator<Item = Record>, output: Output) -> Vec<String> {
+    let json = |r: &Record| serde_json::to_string(r).unwrap();
+    let row = |cells: Vec<String>| match output {
+        Output::Tsv => cells
+            .iter()
+            .map(|c| c.replace(['\t', '\n', '\r'], " "))
+            .collect::<Vec<_>>()
+            .join("\t"),
+        _ => cells
+            .iter()
+            .map(|c| csv_cell(c))
+            .collect::<Vec<_>>()
+            .join(","),
+    };
+    match output {
+        Output::Table => vec![],
+        Output::Json => {
+            let records: Vec<_> = records.collect();
+            vec![serde_json::to_string_pretty(&records).unwrap()]
+        }
+        Output::Jsonl => records.map(|r| json(&r)).collect(),
+        Output::Csv | Output::Tsv => {
+            let header = COLUMNS.iter().map(|c| c.to_string()).collect();
+            std::iter::once(row(header))
+                .chain(records.map(|r| row(r.columns())))
+                .collect()
+        }
+    }
+}
+
+fn csv_cell(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_records() -> Result<(), Box<dyn std::error::Error>> {
+        let mut entry = Entry::new("swan", "10.1145/2486001.2486012");
+        entry.parse_bibtex(String::from(
+            "@inproceedings{swan, title = {Achieving high utilization, with software-driven WAN}, \
+             author = {Hong, Chi-Yao and von Neumann, John}, year = {2013}, booktitle = {SIGCOMM}, \
+             keywords = {sdn, wan}}",
+        ))?;
+        let record = Record::new(3, &entry);
+        assert_eq!(record.authors, vec!["Hong, Chi-Yao", "von Neumann, John"]);
+        assert_eq!(record.year, Some(2013));
+        assert_eq!(record.venue.as_deref(), Some("SIGCOMM"));
+        assert_eq!(record.tags, vec!["sdn", "wan"]);
+
+        let other = Record::new(0, &Entry::new("other", "1904.12901"));
+        let key: SortKey = "year:desc".parse()?;
+        assert_eq!(record.compare(&other, &key), Ordering::Less);
+        assert_eq!(other.cell(Column::Arxiv), "1904.12901");
+        assert!("year:up".parse::<SortKey>().is_err());
+
+        let jsonl = format_records(vec![Record::new(3, &entry)].into_iter(), Output::Jsonl);
+        let value: serde_json::Value = serde_json::from_str(&jsonl[0])?;
+        assert_eq!(value["identifiers"]["doi"], "10.1145/2486001.2486012");
+        let csv = format_records(vec![record].into_iter(), Output::Csv);
+        assert_eq!(csv[0], COLUMNS.join(","));
+        assert!(csv[1].starts_with(
+            "3,swan,\"Achieving high utilization, with software-driven WAN\",\"Hong, Chi-Yao; von Neumann, John\",2013,SIGCOMM,"
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,921 @@
+// render entries as formatted references with CSL styles,
+// only a subset of CSL 1.0 is supported, unsupported elements are ignored
+use biblatex::{ChunksExt, DateValue, EntryType, Person};
+use roxmltree::{Document, Node};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+// names of BUILTIN_STYLES, e.g. for completion
+pub const STYLE_NAMES: &[&str] = &["apa", "ieee", "acm", "chicago"];
+
+const BUILTIN_STYLES: &[(&str, &str)] = &[
+    ("apa", include_str!("../styles/apa.csl")),
+    ("ieee", include_str!("../styles/ieee.csl")),
+    ("acm", include_str!("../styles/acm.csl")),
+    ("chicago", include_str!("../styles/chicago.csl")),
+];
+
+const MONTHS: &[&str] = &[
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// en-US terms: (name, singular, plural, short singular, short plural)
+const TERMS: &[(&str, &str, &str, &str, &str)] = &[
+    ("and", "and", "and", "&", "&"),
+    ("et-al", "et al.", "et al.", "et al.", "et al."),
+    ("in", "in", "in", "in", "in"),
+    ("edition", "edition", "editions", "ed.", "eds."),
+    ("editor", "editor", "editors", "ed.", "eds."),
+    ("page", "page", "pages", "p.", "pp."),
+    ("volume", "volume", "volumes", "vol.", "vols."),
+    ("issue", "issue", "issues", "no.", "nos."),
+    ("no date", "no date", "no date", "n.d.", "n.d."),
+    ("accessed", "accessed", "accessed", "accessed", "accessed"),
+    (
+        "retrieved",
+        "retrieved",
+        "retrieved",
+        "retrieved",
+        "retrieved",
+    ),
+    ("from", "from", "from", "from", "from"),
+    (
+        "available at",
+        "available at",
+        "available at",
+        "available at",
+        "available at",
+    ),
+    ("online", "online", "online", "online", "online"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Markdown,
+    Html,
+    Rtf,
+}
+
+impl OutputFormat {
+    pub const NAMES: &'static [&'static str] = &["text", "markdown", "html", "rtf"];
+    // with aliases
+    pub const VALUES: &'static [&'static str] =
+        &["text", "markdown", "html", "rtf", "txt", "plain", "md"];
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" | "txt" | "plain" => Ok(OutputFormat::Text),
+            "markdown" | "md" => Ok(OutputFormat::Markdown),
+            "html" => Ok(OutputFormat::Html),
+            "rtf" => Ok(OutputFormat::Rtf),
+            _ => Err(format!(
+                "unknown format '{}', expect text, markdown, html or rtf",
+                s
+            )),
+        }
+    }
+}
+
+// CSL variables of one entry
+#[derive(Default, Clone)]
+pub struct Item {
+    kind: String,
+    texts: HashMap<&'static str, String>,
+    names: HashMap<&'static str, Vec<Person>>,
+    issued: Option<(i32, Option<u8>, Option<u8>)>, // year, month and day starting from 1
+}
+
+impl Item {
+    pub fn from_entry(key: &str, e: &biblatex::Entry) -> Item {
+        let kind = csl_type(e);
+        let mut item = Item::default();
+        let mut set = |var: &'static str, value: Option<String>| {
+            if let Some(v) = value.filter(|v| !v.trim().is_empty()) {
+                item.texts.insert(var, v);
+            }
+        };
+        let verbatim = |field: &str| e.get(field).map(|c| c.format_verbatim());
+        set("citation-key", Some(String::from(key)));
+        set("title", verbatim("title"));
+        set(
+            "container-title",
+            verbatim("journaltitle")
+                .or_else(|| verbatim("journal"))
+                .or_else(|| verbatim("booktitle")),
+        );
+        set("collection-title", verbatim("series"));
+        set(
+            "publisher",
+            verbatim("publisher")
+                .or_else(|| verbatim("school"))
+                .or_else(|| verbatim("institution"))
+                .or_else(|| verbatim("organization")),
+        );
+        set(
+            "publisher-place",
+            verbatim("address").or_else(|| verbatim("location")),
+        );
+        set("volume", verbatim("volume"));
+        set("edition", verbatim("edition"));
+        set("page", verbatim("pages").map(|p| page_range(&p)));
+        set("DOI", verbatim("doi"));
+        set("URL", verbatim("url"));
+        set("note", verbatim("note"));
+        set("genre", verbatim("type"));
+        if kind == "article-journal" {
+            set("issue", verbatim("number").or_else(|| verbatim("issue")));
+        } else {
+            set("number", verbatim("number"));
+        }
+        if let Some(eprint) = verbatim("eprint") {
+            set("archive", Some(String::from("arXiv")));
+            set("number", Some(format!("arXiv:{}", eprint)));
+        }
+        item.kind = kind;
+        if let Some(authors) = e.author() {
+            item.names.insert("author", authors);
+        }
+        if let Some((editors, _)) = e.editors().into_iter().next() {
+            item.names.insert("editor", editors);
+        }
+        if let Some(date) = e.date() {
+            let d = match date.value {
+                DateValue::At(d) | DateValue::After(d) | DateValue::Before(d) => d,
+                DateValue::Between(d, _) => d,
+            };
+            item.issued = Some((d.year, d.month.map(|m| m + 1), d.day.map(|d| d + 1)));
+        }
+        item
+    }
+
+    fn text(&self, var: &str) -> Option<String> {
+        self.texts.get(var).cloned()
+    }
+
+    fn has(&self, var: &str) -> bool {
+        self.texts.contains_key(var)
+            || self.names.contains_key(var)
+            || (var == "issued" && self.issued.is_some())
+    }
+}
+
+fn csl_type(e: &biblatex::Entry) -> String {
+    let t = match &e.entry_type {
+        EntryType::Article => "article-journal",
+        EntryType::InProceedings => "paper-conference",
+        EntryType::Book | EntryType::MvBook => "book",
+        EntryType::InBook | EntryType::InCollection | EntryType::BookInBook => "chapter",
+        EntryType::PhdThesis | EntryType::MastersThesis | EntryType::Thesis => "thesis",
+        EntryType::TechReport | EntryType::Report => "report",
+        EntryType::Online => "webpage",
+        EntryType::Dataset => "dataset",
+        EntryType::Software => "software",
+        _ => "article",
+    };
+    String::from(t)
+}
+
+// "1--10" or "1-10" -> "1–10"
+fn page_range(pages: &str) -> String {
+    pages.replace("--", "\u{2013}").replace('-', "\u{2013}")
+}
+
+pub fn builtin_style(name: &str) -> Option<&'static str> {
+    BUILTIN_STYLES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, s)| *s)
+}
+
+#[derive(Clone, Default, PartialEq, Debug)]
+struct Span {
+    text: String,
+    italic: bool,
+    bold: bool,
+}
+
+// rendered output and whether it used variables, to suppress empty groups
+#[derive(Default)]
+struct Out {
+    spans: Vec<Span>,
+    var_called: bool,
+    var_rendered: bool,
+}
+
+impl Out {
+    fn plain(text: String) -> Out {
+        Out {
+            spans: vec![Span {
+                text,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.spans.iter().all(|s| s.text.is_empty())
+    }
+
+    fn to_plain(&self) -> String {
+        self.spans.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    fn append(&mut self, other: Out, delimiter: &str) {
+        self.var_called |= other.var_called;
+        self.var_rendered |= other.var_rendered;
+        if other.is_empty() {
+            return;
+        }
+        if !self.is_empty() && !delimiter.is_empty() {
+            self.spans.push(Span {
+                text: String::from(delimiter),
+                ..Default::default()
+            });
+        }
+        self.spans.extend(other.spans);
+    }
+}
+
+struct Renderer<'a, 'input> {
+    macros: HashMap<&'a str, Node<'a, 'input>>,
+    // variables used as substitutes of names, not rendered again for the same item
+    substituted: RefCell<HashSet<String>>,
+}
+
+pub fn render(
+    style: &str,
+    items: &[Item],
+    format: OutputFormat,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let doc = Document::parse(style)?;
+    let root = doc.root_element();
+    let macros = root
+        .children()
+        .filter(|n| n.has_tag_name("macro"))
+        .filter_map(|n| n.attribute("name").map(|name| (name, n)))
+        .collect();
+    let renderer = Renderer {
+        macros,
+        substituted: RefCell::default(),
+    };
+    let bib = root
+        .children()
+        .find(|n| n.has_tag_name("bibliography"))
+        .ok_or("no bibliography in style")?;
+    let layout = bib
+        .children()
+        .find(|n| n.has_tag_name("layout"))
+        .ok_or("no layout in bibliography")?;
+
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    if let Some(sort) = bib.children().find(|n| n.has_tag_name("sort")) {
+        let keys: Vec<Vec<String>> = items
+            .iter()
+            .map(|item| renderer.sort_keys(sort, item))
+            .collect();
+        // items with empty keys go last
+        order.sort_by_key(|i| {
+            keys[*i]
+                .iter()
+                .map(|k| (k.is_empty(), k))
+                .collect::<Vec<_>>()
+        });
+    }
+    let mut result = Vec::new();
+    for (i, &idx) in order.iter().enumerate() {
+        let mut item = items[idx].clone();
+        item.texts.insert("citation-number", (i + 1).to_string());
+        renderer.substituted.borrow_mut().clear();
+        let out = renderer.render_layout(layout, &item);
+        result.push(format_spans(&out.spans, format));
+    }
+    Ok(result)
+}
+
+impl<'a, 'input> Renderer<'a, 'input> {
+    fn sort_keys(&self, sort: Node, item: &Item) -> Vec<String> {
+        self.substituted.borrow_mut().clear();
+        sort.children()
+            .filter(|n| n.has_tag_name("key"))
+            .map(|key| {
+                if let Some(m) = key.attribute("macro").and_then(|m| self.macros.get(m)) {
+                    return self.render_children(*m, item, "").to_plain().to_lowercase();
+                }
+                match key.attribute("variable") {
+                    Some("issued") => item.issued.map_or(String::new(), |(y, m, d)| {
+                        format!("{:04}{:02}{:02}", y, m.unwrap_or(0), d.unwrap_or(0))
+                    }),
+                    Some(var) if item.names.contains_key(var) => item.names[var]
+                        .iter()
+                        .map(|p| format!("{} {}", p.name, p.given_name))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                        .to_lowercase(),
+                    Some(var) => item.text(var).unwrap_or_default().to_lowercase(),
+                    None => String::new(),
+                }
+            })
+            .collect()
+    }
+
+    fn render_layout(&self, layout: Node, item: &Item) -> Out {
+        let delimiter = layout.attribute("delimiter").unwrap_or("");
+        let out = self.render_children(layout, item, delimiter);
+        decorate(layout, out)
+    }
+
+    fn render_children(&self, node: Node, item: &Item, delimiter: &str) -> Out {
+        let mut out = Out::default();
+        for child in node.children().filter(|n| n.is_element()) {
+            out.append(self.render_node(child, item), delimiter);
+        }
+        out
+    }
+
+    fn render_node(&self, node: Node, item: &Item) -> Out {
+        let tag = node.tag_name().name();
+        if tag != "names" && self.is_substituted(node) {
+            return Out {
+                var_called: true,
+                ..Default::default()
+            };
+        }
+        let out = match tag {
+            "text" => self.render_text(node, item),
+            "number" => render_variable(node, item),
+            "date" => render_date(node, item),
+            "names" => self.render_names(node, item, None),
+            "label" => render_label(node, item),
+            "group" => {
+                let delimiter = node.attribute("delimiter").unwrap_or("");
+                let out = self.render_children(node, item, delimiter);
+                // a group is suppressed if it calls variables but all of them are empty
+                if out.var_called && !out.var_rendered {
+                    Out::default()
+                } else {
+                    out
+                }
+            }
+            "choose" => self.render_choose(node, item),
+            _ => Out::default(),
+        };
+        decorate(node, out)
+    }
+
+    fn render_text(&self, node: Node, item: &Item) -> Out {
+        if node.attribute("variable").is_some() {
+            render_variable(node, item)
+        } else if let Some(name) = node.attribute("macro") {
+            match self.macros.get(name) {
+                Some(m) => self.render_children(*m, item, ""),
+                None => Out::default(),
+            }
+        } else if let Some(term) = node.attribute("term") {
+            let plural = node.attribute("plural") == Some("true");
+            Out::plain(lookup_term(term, node.attribute("form"), plural))
+        } else if let Some(value) = node.attribute("value") {
+            Out::plain(String::from(value))
+        } else {
+            Out::default()
+        }
+    }
+
+    fn is_substituted(&self, node: Node) -> bool {
+        match node.attribute("variable") {
+            Some(var) => self.substituted.borrow().contains(var),
+            None => false,
+        }
+    }
+
+    // names in `substitute` inherit `name` and `label` of the names they replace
+    fn render_names(&self, node: Node, item: &Item, parent: Option<Node>) -> Out {
+        let vars = node.attribute("variable").unwrap_or("");
+        let child = |tag| {
+            let own = node.children().find(|n| n.has_tag_name(tag));
+            own.or_else(|| parent.and_then(|p| p.children().find(|n| n.has_tag_name(tag))))
+        };
+        let name_node = child("name");
+        let label_node = child("label");
+        let mut out = Out {
+            var_called: true,
+            ..Default::default()
+        };
+        let delimiter = node.attribute("delimiter").unwrap_or(", ");
+        for var in vars.split_whitespace() {
+            if self.substituted.borrow().contains(var) {
+                continue;
+            }
+            let persons = match item.names.get(var) {
+                Some(p) if !p.is_empty() => p,
+                _ => continue,
+            };
+            let mut names = Out::plain(format_names(persons, name_node));
+            if let Some(label) = label_node {
+                let form = label.attribute("form");
+                let text = lookup_term(var, form, persons.len() > 1);
+                names.append(decorate(label, Out::plain(text)), "");
+            }
+            names.var_rendered = true;
+            out.append(names, delimiter);
+        }
+        if !out.var_rendered {
+            if let Some(substitute) = node.children().find(|n| n.has_tag_name("substitute")) {
+                for child in substitute.children().filter(|n| n.is_element()) {
+                    let sub =
+                        if child.has_tag_name("names") && child.attribute("variable").is_some() {
+                            decorate(child, self.render_names(child, item, Some(node)))
+                        } else {
+                            self.render_node(child, item)
+                        };
+                    if !sub.is_empty() {
+                        let vars = child.attribute("variable").unwrap_or("");
+                        let mut substituted = self.substituted.borrow_mut();
+                        substituted.extend(vars.split_whitespace().map(String::from));
+                        return sub;
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    fn render_choose(&self, node: Node, item: &Item) -> Out {
+        for branch in node.children().filter(|n| n.is_element()) {
+            let matched = match branch.tag_name().name() {
+                "if" | "else-if" => test_condition(branch, item),
+                "else" => true,
+                _ => false,
+            };
+            if matched {
+                return self.render_children(branch, item, "");
+            }
+        }
+        Out::default()
+    }
+}
+
+fn test_condition(node: Node, item: &Item) -> bool {
+    let mut tests: Vec<bool> = Vec::new();
+    if let Some(types) = node.attribute("type") {
+        tests.extend(types.split_whitespace().map(|t| t == item.kind));
+    }
+    if let Some(vars) = node.attribute("variable") {
+        tests.extend(vars.split_whitespace().map(|v| item.has(v)));
+    }
+    if let Some(vars) = node.attribute("is-numeric") {
+        tests.extend(vars.split_whitespace().map(|v| {
+            item.text(v)
+                .is_some_and(|t| !t.is_empty() && t.chars().all(|c| c.is_ascii_digit()))
+        }));
+    }
+    match node.attribute("match").unwrap_or("all") {
+        "any" => tests.iter().any(|t| *t),
+        "none" => !tests.iter().any(|t| *t),
+        _ => tests.iter().all(|t| *t),
+    }
+}
+
+fn render_variable(node: Node, item: &Item) -> Out {
+    let var = node.attribute("variable").unwrap_or("");
+    let value = if var == "issued" {
+        item.issued.map(|(y, _, _)| y.to_string())
+    } else {
+        item.text(var)
+    };
+    let mut out = match value {
+        Some(v) => Out::plain(v),
+        None => Out::default(),
+    };
+    out.var_called = true;
+    out.var_rendered = !out.is_empty();
+    out
+}
+
+fn render_label(node: Node, item: &Item) -> Out {
+    let var = node.attribute("variable").unwrap_or("");
+    let value = match item.text(var) {
+        Some(v) => v,
+        None => return Out::default(),
+    };
+    let plural = match node.attribute("plural") {
+        Some("always") => true,
+        Some("never") => false,
+        _ => value.contains('\u{2013}') || value.contains(',') || value.contains('&'),
+    };
+    Out::plain(lookup_term(var, node.attribute("form"), plural))
+}
+
+fn render_date(node: Node, item: &Item) -> Out {
+    let mut out = Out {
+        var_called: true,
+        ..Default::default()
+    };
+    let (year, month, day) = match (node.attribute("variable"), item.issued) {
+        (Some("issued"), Some(d)) => d,
+        _ => return out,
+    };
+    let parts: Vec<Node> = node
+        .children()
+        .filter(|n| n.has_tag_name("date-part"))
+        .collect();
+    if parts.is_empty() {
+        // localized forms
+        let text = match (node.attribute("form"), month, day) {
+            (Some("numeric"), Some(m), Some(d)) => format!("{}/{}/{}", m, d, year),
+            (Some("numeric"), Some(m), None) => format!("{}/{}", m, year),
+            (_, Some(m), Some(d)) => format!("{} {}, {}", MONTHS[m as usize - 1], d, year),
+            (_, Some(m), None) => format!("{} {}", MONTHS[m as usize - 1], year),
+            _ => year.to_string(),
+        };
+        out.append(Out::plain(text), "");
+    }
+    let delimiter = node.attribute("delimiter").unwrap_or(" ");
+    for part in parts {
+        let text = match part.attribute("name") {
+            Some("year") => Some(year.to_string()),
+            Some("month") => month.map(|m| match part.attribute("form") {
+                Some("numeric") => m.to_string(),
+                Some("numeric-leading-zeros") => format!("{:02}", m),
+                Some("short") => MONTHS[m as usize - 1][..3].to_string(),
+                _ => MONTHS[m as usize - 1].to_string(),
+            }),
+            Some("day") => day.map(|d| d.to_string()),
+            _ => None,
+        };
+        if let Some(t) = text {
+            out.append(decorate(part, Out::plain(t)), delimiter);
+        }
+    }
+    out.var_rendered = !out.is_empty();
+    out
+}
+
+fn lookup_term(name: &str, form: Option<&str>, plural: bool) -> String {
+    match TERMS.iter().find(|t| t.0 == name) {
+        Some(t) => String::from(match (form, plural) {
+            (Some("short"), false) => t.3,
+            (Some("short"), true) => t.4,
+            (_, false) => t.1,
+            (_, true) => t.2,
+        }),
+        None => String::new(),
+    }
+}
+
+fn format_names(persons: &[Person], name: Option<Node>) -> String {
+    let attr = |key: &str| name.and_then(|n| n.attribute(key));
+    let delimiter = attr("delimiter").unwrap_or(", ");
+    let sort_order = attr("name-as-sort-order");
+    let sort_separator = attr("sort-separator").unwrap_or(", ");
+    let initialize = attr("initialize-with");
+    let short = attr("form") == Some("short");
+    let et_al_min: usize = attr("et-al-min").and_then(|v| v.parse().ok()).unwrap_or(0);
+    let et_al_use: usize = attr("et-al-use-first")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(1);
+    let and = match attr("and") {
+        Some("symbol") => Some("&"),
+        Some("text") => Some("and"),
+        _ => None,
+    };
+
+    let truncated = et_al_min > 0 && persons.len() >= et_al_min;
+    let shown = if truncated {
+        &persons[..et_al_use.min(persons.len())]
+    } else {
+        persons
+    };
+    let formatted: Vec<String> = shown
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let family = if p.prefix.is_empty() {
+                p.name.clone()
+            } else {
+                format!("{} {}", p.prefix, p.name)
+            };
+            if short || p.given_name.is_empty() {
+                return family;
+            }
+            let given = match initialize {
+                Some(sep) => initials(&p.given_name, sep),
+                None => p.given_name.clone(),
+            };
+            let inverted = match sort_order {
+                Some("all") => true,
+                Some("first") => i == 0,
+                _ => false,
+            };
+            if inverted {
+                format!("{}{}{}", family, sort_separator, given)
+            } else {
+                format!("{} {}", given, family)
+            }
+        })
+        .collect();
+
+    if truncated {
+        let sep = if shown.len() > 1 || sort_order.is_some() {
+            delimiter
+        } else {
+            " "
+        };
+        return format!("{}{}{}", formatted.join(delimiter), sep, "et al.");
+    }
+    match (formatted.len(), and) {
+        (0, _) => String::new(),
+        (1, _) => formatted[0].clone(),
+        (n, Some(and)) => {
+            let precedes_last = match attr("delimiter-precedes-last") {
+                Some("always") => true,
+                Some("never") => false,
+                // contextual, or inverted first name which contains a comma
+                _ => n > 2 || (sort_order.is_some() && sort_separator.contains(',')),
+            };
+            let head = formatted[..n - 1].join(delimiter);
+            let sep = if precedes_last { delimiter } else { " " };
+            format!("{}{}{} {}", head, sep, and, formatted[n - 1])
+        }
+        (_, None) => formatted.join(delimiter),
+    }
+}
+
+// "John Ronald" -> "J. R.", "Chi-Yao" -> "C.-Y."
+fn initials(given: &str, sep: &str) -> String {
+    given
+        .split_whitespace()
+        .map(|word| {
+            word.split('-')
+                .filter_map(|part| part.chars().next())
+                .map(|c| format!("{}{}", c, sep.trim_end()))
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect::<Vec<_>>()
+        .join(if sep.ends_with(' ') { " " } else { "" })
+}
+
+// apply formatting, quotes, text-case and affixes of an element
+fn decorate(node: Node, mut out: Out) -> Out {
+    if out.is_empty() {
+        return out;
+    }
+    if let Some(case) = node.attribute("text-case") {
+        apply_case(&mut out.spans, case);
+    }
+    if node.attribute("strip-periods") == Some("true") {
+        out.spans
+            .iter_mut()
+            .for_each(|s| s.text.retain(|c| c != '.'));
+    }
+    let italic = node.attribute("font-style") == Some("italic");
+    let bold = node.attribute("font-weight") == Some("bold");
+    for s in out.spans.iter_mut() {
+        s.italic |= italic;
+        s.bold |= bold;
+    }
+    let quotes = node.attribute("quotes") == Some("true");
+    let mut suffix = node.attribute("suffix").unwrap_or("");
+    // avoid double periods like "et al.."
+    if suffix.starts_with('.') && out.to_plain().ends_with('.') {
+        suffix = &suffix[1..];
+    }
+    if quotes {
+        out.spans.insert(0, plain_span("\u{201c}"));
+        // punctuation goes inside quotes in en-US
+        if suffix.starts_with(',') || suffix.starts_with('.') {
+            out.spans.push(plain_span(&suffix[..1]));
+            suffix = &suffix[1..];
+        }
+        out.spans.push(plain_span("\u{201d}"));
+    }
+    if let Some(prefix) = node.attribute("prefix") {
+        out.spans.insert(0, plain_span(prefix));
+    }
+    out.spans.push(plain_span(suffix));
+    out
+}
+
+fn plain_span(text: &str) -> Span {
+    Span {
+        text: String::from(text),
+        ..Default::default()
+    }
+}
+
+fn apply_case(spans: &mut [Span], case: &str) {
+    let mut first = true;
+    for span in spans.iter_mut() {
+        span.text = match case {
+            "lowercase" => span.text.to_lowercase(),
+            "uppercase" => span.text.to_uppercase(),
+            "capitalize-first" if first => capitalize(&span.text),
+            "sentence" if first => capitalize(&span.text.to_lowercase()),
+            "sentence" => span.text.to_lowercase(),
+            "title" => span
+                .text
+                .split(' ')
+                .map(capitalize)
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => span.text.clone(),
+        };
+        first &= span.text.is_empty();
+    }
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn format_spans(spans: &[Span], format: OutputFormat) -> String {
+    let mut result = String::new();
+    for span in spans.iter().filter(|s| !s.text.is_empty()) {
+        let text = match format {
+            OutputFormat::Text => span.text.clone(),
+            OutputFormat::Markdown => span.text.replace('*', "\\*").replace('_', "\\_"),
+            OutputFormat::Html => span
+                .text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;"),
+            OutputFormat::Rtf => escape_rtf(&span.text),
+        };
+        let text = match (format, span.italic, span.bold) {
+            (OutputFormat::Markdown, true, true) => format!("***{}***", text),
+            (OutputFormat::Markdown, true, false) => format!("*{}*", text),
+            (OutputFormat::Markdown, false, true) => format!("**{}**", text),
+            (OutputFormat::Html, true, true) => format!("<b><i>{}</i></b>", text),
+            (OutputFormat::Html, true, false) => format!("<i>{}</i>", text),
+            (OutputFormat::Html, false, true) => format!("<b>{}</b>", text),
+            (OutputFormat::Rtf, true, true) => format!("{{\\b\\i {}}}", text),
+            (OutputFormat::Rtf, true, false) => format!("{{\\i {}}}", text),
+            (OutputFormat::Rtf, false, true) => format!("{{\\b {}}}", text),
+            _ => text,
+        };
+        result.push_str(&text);
+    }
+    match format {
+        OutputFormat::Html => format!("<div class=\"csl-entry\">{}</div>", result),
+        _ => result,
+    }
+}
+
+fn escape_rtf(text: &str) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '{' | '}' => {
+                out.push('\\');
+                out.push(c);
+            }
+            c if c.is_ascii() => out.push(c),
+            // rtf unicode escape takes a signed 16-bit number and a fallback character
+            c => {
+                let mut buf = [0u16; 2];
+                for unit in c.encode_utf16(&mut buf) {
+                    out.push_str(&format!("\\u{}?", *unit as i16));
+                }
+            }
+        }
+    }
+    out
+}
+
+// wrap rendered references into a complete document if format needs it
+pub fn wrap_document(refs: &[String], format: OutputFormat) -> String {
+    match format {
+        OutputFormat::Rtf => {
+            let body: Vec<String> = refs.iter().map(|r| format!("{}\\par", r)).collect();
+            format!("{{\\rtf1\\ansi\\deff0\n{}\n}}", body.join("\n"))
+        }
+        OutputFormat::Html => {
+            format!("<div class=\"csl-bib-body\">\n{}\n</div>", refs.join("\n"))
+        }
+        OutputFormat::Markdown => refs
+            .iter()
+            .map(|r| format!("- {}", r))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        OutputFormat::Text => refs.join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use biblatex::Bibliography;
+
+    fn item(bibtex: &str) -> Item {
+        let bib = Bibliography::parse(bibtex).unwrap();
+        let e = bib.iter().next().unwrap();
+        Item::from_entry(&e.key, e)
+    }
+
+    fn sample() -> Item {
+        item(
+            "@inproceedings{swan, title = {Achieving High Utilization with Software-Driven {WAN}}, \
+             author = {Hong, Chi-Yao and Kandula, Srikanth and Mahajan, Ratul}, \
+             booktitle = {Proceedings of the ACM SIGCOMM 2013 Conference}, \
+             pages = {15--26}, year = {2013}, doi = {10.1145/2486001.2486012}}",
+        )
+    }
+
+    #[test]
+    fn test_render_styles() -> Result<(), Box<dyn std::error::Error>> {
+        let items = [sample()];
+        let apa = render(builtin_style("apa").unwrap(), &items, OutputFormat::Text)?;
+        assert_eq!(
+            apa[0],
+            "Hong, C.-Y., Kandula, S., & Mahajan, R. (2013). Achieving High Utilization \
+             with Software-Driven WAN. In Proceedings of the ACM SIGCOMM 2013 Conference \
+             (pp. 15–26). https://doi.org/10.1145/2486001.2486012"
+        );
+        let ieee = render(
+            builtin_style("ieee").unwrap(),
+            &items,
+            OutputFormat::Markdown,
+        )?;
+        assert_eq!(
+            ieee[0],
+            "[1] C.-Y. Hong, S. Kandula, and R. Mahajan, “Achieving High Utilization with \
+             Software-Driven WAN,” in *Proceedings of the ACM SIGCOMM 2013 Conference*, \
+             2013, pp. 15–26, doi: 10.1145/2486001.2486012."
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_acm_chicago() -> Result<(), Box<dyn std::error::Error>> {
+        let items = [
+            sample(),
+            // no authors, no date, and an editor only
+            item("@misc{anon, title = {Untitled Notes}}"),
+            item("@book{nodate, title = {A Book}, author = {Doe, Jane}, publisher = {Press}}"),
+            item(
+                "@book{edited, title = {Collected Papers}, editor = {Smith, John}, \
+                 publisher = {Press}, year = {2001}}",
+            ),
+        ];
+        let acm = render(builtin_style("acm").unwrap(), &items, OutputFormat::Text)?;
+        assert_eq!(
+            acm,
+            [
+                "Jane Doe. A Book. Press.",
+                "Chi-Yao Hong, Srikanth Kandula, and Ratul Mahajan. 2013. Achieving High \
+                 Utilization with Software-Driven WAN. In Proceedings of the ACM SIGCOMM 2013 \
+                 Conference. 15–26. https://doi.org/10.1145/2486001.2486012",
+                "John Smith (Ed.). 2001. Collected Papers. Press.",
+                // items without authors go last
+                "Untitled Notes.",
+            ]
+        );
+        let chicago = render(
+            builtin_style("chicago").unwrap(),
+            &items,
+            OutputFormat::Markdown,
+        )?;
+        assert_eq!(
+            chicago,
+            [
+                "Doe, Jane. n.d. *A Book*. Press.",
+                "Hong, Chi-Yao, Srikanth Kandula, and Ratul Mahajan. 2013. “Achieving High \
+                 Utilization with Software-Driven WAN.” In *Proceedings of the ACM SIGCOMM 2013 \
+                 Conference*, 15–26. https://doi.org/10.1145/2486001.2486012.",
+                "Smith, John, ed. 2001. *Collected Papers*. Press.",
+                // the title substitutes authors and is not repeated
+                "Untitled Notes. n.d.",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_style_names() {
+        let names: Vec<_> = BUILTIN_STYLES.iter().map(|(n, _)| *n).collect();
+        assert_eq!(names, STYLE_NAMES);
+    }
+}
@@ -1,17 +1,158 @@
 use rustyline::completion::{Completer, FilenameCompleter, Pair};
 use rustyline::config::OutputStreamType;
 use rustyline::hint::{Hinter, HistoryHinter};
-use rustyline::{CompletionType, Config, Context, EditMode, Editor};
+use rustyline::{CompletionType, Config, Context, EditMode};
 use rustyline_derive::{Helper, Highlighter, Validator};
 
+use crate::cli::{ArgKind, CommandArgs};
+use crate::config;
+use crate::utils;
+
 pub use rustyline::error::ReadlineError;
+pub use rustyline::Editor;
+
+// what is expected at the cursor
+#[derive(Clone, Debug, PartialEq)]
+enum Expected {
+    Arg(ArgKind),
+    // command names or options
+    Words(Vec<String>),
+}
 
 #[derive(Helper, Validator, Highlighter)]
-#[allow(dead_code)]
 pub struct RLHelper {
     completer: FilenameCompleter,
     hinter: HistoryHinter,
+    commands: &'static [CommandArgs],
+    // id, citekey and title
+    entries: Vec<(usize, String, String)>,
+    tags: Vec<String>,
+}
+
+impl RLHelper {
+    fn new(commands: &'static [CommandArgs]) -> RLHelper {
+        RLHelper {
+            completer: FilenameCompleter::new(),
+            hinter: HistoryHinter {},
+            commands,
+            entries: vec![],
+            tags: vec![],
+        }
+    }
+
+    // entries of current library, to complete ids and tags
+    pub fn set_entries(&mut self, entries: Vec<(usize, String, String)>, tags: Vec<String>) {
+        self.entries = entries;
+        self.tags = tags;
+    }
+
+    fn command_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .commands
+            .iter()
+            .map(|c| c.names[0].to_owned())
+            .collect();
+        names.push(String::from("help"));
+        names
+    }
+
+    // kind of the word after `words`
+    fn expected(&self, words: &[&str], word: &str) -> Expected {
+        let (first, args) = match words.split_first() {
+            None => return Expected::Words(self.command_names()),
+            Some(w) => w,
+        };
+        if *first == "help" {
+            return match args {
+                [] => Expected::Words(self.command_names()),
+                _ => Expected::Arg(ArgKind::Other),
+            };
+        }
+        let command = match self.commands.iter().find(|c| c.names.contains(first)) {
+            None => return Expected::Arg(ArgKind::Other),
+            Some(c) => c,
+        };
+        let opts = || command.opts.iter().flat_map(|o| o.iter());
+        // options before the cursor, an option taking a value consumes the next word
+        let mut n = 0;
+        let mut value = None;
+        for w in args {
+            if value.take().is_some() {
+                continue;
+            }
+            let opt = if let Some(long) = w.strip_prefix("--") {
+                let long = long.split('=').next();
+                opts().find(|o| Some(o.0) == long)
+            } else if w.starts_with('-') && w.len() == 2 {
+                opts().find(|o| o.1 == w.chars().nth(1))
+            } else {
+                n += 1;
+                continue;
+            };
+            if !w.contains('=') {
+                value = opt.and_then(|o| o.2);
+            }
+        }
+        if let Some(kind) = value {
+            return Expected::Arg(kind);
+        }
+        if word.starts_with('-') {
+            return Expected::Words(opts().map(|o| format!("--{}", o.0)).collect());
+        }
+        let kind = match command.positionals.get(n) {
+            Some(kind) => *kind,
+            None => match command.positionals.last() {
+                Some(kind) if command.repeated => *kind,
+                _ => ArgKind::Other,
+            },
+        };
+        Expected::Arg(kind)
+    }
+
+    fn candidates(&self, expected: Expected, word: &str) -> Vec<Pair> {
+        let pair = |display: String, replacement: String| Pair {
+            display,
+            replacement,
+        };
+        let values = |values: Vec<String>| {
+            values
+                .into_iter()
+                .filter(|v| v.starts_with(word))
+                .map(|v| pair(v.clone(), v))
+                .collect()
+        };
+        let kind = match expected {
+            Expected::Words(words) => return values(words),
+            Expected::Arg(kind) => kind,
+        };
+        match kind {
+            ArgKind::Values(v) => values(v.iter().map(|s| s.to_string()).collect()),
+            // ids and citekeys both complete to ids, with titles shown
+            ArgKind::Entry => self
+                .entries
+                .iter()
+                .filter(|(id, name, _)| id.to_string().starts_with(word) || name.starts_with(word))
+                .map(|(id, name, title)| {
+                    let display = format!("{} {}: {}", id, name, utils::shorten(title));
+                    pair(display, id.to_string())
+                })
+                .collect(),
+            ArgKind::Query => {
+                let (prefix, tag) = match word.find(':') {
+                    Some(i) if ["tag", "tags"].contains(&&word[..i]) => word.split_at(i + 1),
+                    _ => return vec![],
+                };
+                self.tags
+                    .iter()
+                    .filter(|t| t.starts_with(tag))
+                    .map(|t| pair(t.clone(), format!("{}{}", prefix, t)))
+                    .collect()
+            }
+            ArgKind::Path | ArgKind::Other => vec![],
+        }
+    }
 }
+
 impl Completer for RLHelper {
     type Candidate = Pair;
 
@@ -21,7 +162,13 @@ impl Completer for RLHelper {
         pos: usize,
         ctx: &Context<'_>,
     ) -> Result<(usize, Vec<Pair>), ReadlineError> {
-        self.completer.complete(line, pos, ctx)
+        let start = line[..pos].rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let word = &line[start..pos];
+        match self.expected(&words, word) {
+            Expected::Arg(ArgKind::Path) => self.completer.complete(line, pos, ctx),
+            expected => Ok((start, self.candidates(expected, word))),
+        }
     }
 }
 
@@ -33,18 +180,68 @@ impl Hinter for RLHelper {
     }
 }
 
-pub fn my_editor() -> Editor<RLHelper> {
+// `commands` are the arguments of shell commands, for completion
+pub fn my_editor(commands: &'static [CommandArgs]) -> Editor<RLHelper> {
     let config = Config::builder()
         .history_ignore_space(true)
         .completion_type(CompletionType::List)
-        .edit_mode(EditMode::Emacs)
+        .edit_mode(match config::get().edit_mode {
+            config::EditMode::Emacs => EditMode::Emacs,
+            config::EditMode::Vi => EditMode::Vi,
+        })
         .output_stream(OutputStreamType::Stdout)
         .build();
     let mut editor = Editor::with_config(config);
-    let helper = RLHelper {
-        completer: FilenameCompleter::new(),
-        hinter: HistoryHinter {},
-    };
-    editor.set_helper(Some(helper));
+    editor.set_helper(Some(RLHelper::new(commands)));
     editor
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::cli::COMMAND_ARGS;
+
+    fn complete(helper: &RLHelper, line: &str) -> Vec<String> {
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let words: Vec<&str> = line[..start].split_whitespace().collect();
+        let expected = helper.expected(&words, &line[start..]);
+        if expected == Expected::Arg(ArgKind::Path) {
+            return vec![String::from("<path>")];
+        }
+        let pairs = helper.candidates(expected, &line[start..]);
+        pairs.into_iter().map(|p| p.replacement).collect()
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut helper = RLHelper::new(COMMAND_ARGS);
+        let entries = vec![
+            (
+                0,
+                String::from("swan"),
+                String::from("Achieving high utilization"),
+            ),
+            (1, String::from("b4"), String::from("Globally-deployed SDN")),
+        ];
+        helper.set_entries(entries, vec![String::from("reading"), String::from("sdn")]);
+        assert_eq!(complete(&helper, "de"), vec!["del", "detach"]);
+        assert_eq!(complete(&helper, "rm sw"), vec!["0"]);
+        assert_eq!(complete(&helper, "view "), vec!["0", "1"]);
+        assert_eq!(complete(&helper, "link 0 "), vec!["<path>"]);
+        assert_eq!(complete(&helper, "link "), vec!["0", "1"]);
+        assert_eq!(complete(&helper, "search -f tag:r"), vec!["tag:reading"]);
+        assert_eq!(
+            complete(&helper, "search --fu"),
+            vec!["--fuzzy", "--fulltext"]
+        );
+        assert_eq!(complete(&helper, "cite --format h"), vec!["html"]);
+        assert_eq!(complete(&helper, "cite --style apa 1 "), vec!["0", "1"]);
+        assert_eq!(complete(&helper, "gen --abbrev-file "), vec!["<path>"]);
+        assert_eq!(
+            complete(&helper, "attach 0 s"),
+            vec!["supplement", "slides"]
+        );
+        assert_eq!(complete(&helper, "help qu"), vec!["quit"]);
+    }
+}
@@ -0,0 +1,94 @@
+// versions of the library format and migrations between them,
+// migrations work on raw JSON of entries before they are deserialized
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// 1: libraries without version, entries have a single `path`
+// 2: entries have a list of `attachments`
+// 3: entries have a stable `uid`
+pub const SCHEMA_VERSION: u32 = 3;
+
+// i-th migration upgrades an entry from version i + 1 to i + 2
+const MIGRATIONS: &[fn(&mut Map<String, Value>)] = &[move_path_to_attachments, add_uid];
+
+// upgrade an entry of an older version, newer ones are kept as is
+pub fn migrate_entry(entry: &mut Value, version: u32) {
+    if let Some(map) = entry.as_object_mut() {
+        for migration in MIGRATIONS.iter().skip(version.max(1) as usize - 1) {
+            migration(map);
+        }
+    }
+}
+
+// copy library file before migrating it, e.g. refs.json -> refs.json.v1.bak
+pub fn backup(path: &Path, version: u32) -> Result<PathBuf, Box<dyn Error>> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup = path.with_file_name(name);
+    fs::copy(path, &backup)?;
+    Ok(backup)
+}
+
+// short hash identifying an entry across edits, reorders and merges
+pub fn uid(parts: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for p in parts {
+        hasher.update(p.as_bytes());
+        hasher.update([0u8]);
+    }
+    hex::encode(&hasher.finalize()[..8])
+}
+
+fn move_path_to_attachments(entry: &mut Map<String, Value>) {
+    for field in ["name", "doi", "bibtex", "title", "note"].iter() {
+        entry.entry(*field).or_insert_with(|| json!(""));
+    }
+    let meta = entry.remove("file_meta").unwrap_or(Value::Null);
+    let path = entry.remove("path").unwrap_or(Value::Null);
+    let attachments = entry.entry("attachments").or_insert_with(|| json!([]));
+    if let (Value::String(path), Some(list)) = (path, attachments.as_array_mut()) {
+        let pdf = json!({"kind": "pdf", "target": {"file": path}, "meta": meta});
+        list.insert(0, pdf);
+    }
+}
+
+// derived from content, so that copies of a library migrated
+// independently get the same uids
+fn add_uid(entry: &mut Map<String, Value>) {
+    if entry.contains_key("uid") {
+        return;
+    }
+    let field = |k: &str| {
+        entry
+            .get(k)
+            .and_then(|v| v.as_str())
+            .unwrap_or("")
+            .to_owned()
+    };
+    let uid = uid(&[&field("name"), &field("doi"), &field("bibtex")]);
+    entry.insert(String::from("uid"), json!(uid));
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_migrate_entry() {
+        let mut entry = json!({"name": "swan", "doi": "10.1145/2486001.2486012",
+            "bibtex": "", "title": "", "path": "/tmp/swan.pdf", "tags": ["future"]});
+        migrate_entry(&mut entry, 1);
+        assert_eq!(entry["attachments"][0]["target"]["file"], "/tmp/swan.pdf");
+        assert_eq!(entry["note"], "");
+        assert_eq!(entry["tags"][0], "future");
+        assert!(entry.get("path").is_none());
+        assert_eq!(entry["uid"].as_str().map(|s| s.len()), Some(16));
+        // already migrated entries are unchanged
+        let before = entry.clone();
+        migrate_entry(&mut entry, SCHEMA_VERSION);
+        assert_eq!(entry, before);
+    }
+}
@@ -0,0 +1,553 @@
+// JSON REST API over a library, for web UIs and browser tools
+//
+//   GET    /entries[?q=query]    all entries, or results of a search
+//   GET    /entries/{id}
+//   POST   /entries              add by DOI or arXiv id, {"name": "swan", "id": "10.1145/..."}
+//   DELETE /entries/{id}
+//   POST   /entries/{id}/link    link a pdf file, {"path": "/papers/swan.pdf"}
+//   GET    /bib[?mode=biblatex]  bibtex of all entries, query takes options of `gen`
+//   GET    /entries/{id}/bib
+//   POST   /capture              add the paper of a page, {"url": "...", "html": "...", "pdf": true}
+//   GET    /bookmarklet          page with a bookmarklet posting the current page to /capture
+//
+// requests hold the library lock until their changes are saved, and have to name
+// the server by an IP address or localhost; other routes than /capture only change
+// the library with JSON bodies from pages of the server itself
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{header, Body, HeaderMap, Method, Request, Response, Server, StatusCode};
+use indicatif::ProgressBar;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use structopt::StructOpt;
+use tokio::runtime::Runtime;
+
+use crate::config;
+use crate::downloader::{self, ArxivDownloader};
+use crate::files::{self, FileStatus};
+use crate::normalize::GenOptions;
+use crate::{lint, Library};
+
+const BOOKMARKLET: &str = include_str!("../bookmarklet.js");
+
+type ApiResult = Result<Response<Body>, (StatusCode, String)>;
+
+#[derive(Deserialize)]
+struct NewEntry {
+    name: String,
+    id: String,
+}
+
+#[derive(Deserialize)]
+struct Link {
+    path: PathBuf,
+}
+
+// a page sent by the bookmarklet
+#[derive(Deserialize)]
+struct Capture {
+    url: String,
+    html: String,
+    #[serde(default)]
+    pdf: bool,
+    name: Option<String>,
+}
+
+pub fn serve(lib: Library, addr: SocketAddr) -> Result<(), Box<dyn Error>> {
+    let lib = Arc::new(Mutex::new(lib));
+    let rt = Runtime::new()?;
+    rt.block_on(async {
+        let make_service = make_service_fn(move |_| {
+            let lib = lib.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| handle(lib.clone(), req, addr.port())))
+            }
+        });
+        let server = Server::try_bind(&addr)?.serve(make_service);
+        println!("serving library on http://{}", addr);
+        server.await?;
+        Ok(())
+    })
+}
+
+async fn handle(
+    lib: Arc<Mutex<Library>>,
+    req: Request<Body>,
+    port: u16,
+) -> Result<Response<Body>, Infallible> {
+    let (parts, body) = req.into_parts();
+    let (method, uri) = (parts.method, parts.uri);
+    let request = format!("{} {}", method, uri);
+    if let Err((status, message)) = check_request(&method, uri.path(), &parts.headers, port) {
+        println!("{} {}", request, status);
+        return Ok(error(status, message));
+    }
+    if (&method, uri.path()) == (&Method::GET, "/bookmarklet") {
+        let host = parts
+            .headers
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok());
+        return Ok(bookmarklet(host.unwrap_or("127.0.0.1:8080")));
+    }
+    // pages of any site may send themselves to /capture
+    let cors = uri.path() == "/capture";
+    if cors && method == Method::OPTIONS {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        allow_origin(&mut response);
+        return Ok(response);
+    }
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(b) => b,
+        Err(e) => return Ok(error(StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    // library operations block, e.g. adding an entry fetches its bibtex
+    let result = tokio::task::spawn_blocking(move || {
+        let mut lib = lib.lock().unwrap_or_else(|e| e.into_inner());
+        let query = uri.query().unwrap_or("");
+        route(&mut lib, &method, uri.path(), query, &body)
+    })
+    .await;
+    let mut response = match result {
+        Ok(Ok(r)) => r,
+        Ok(Err((status, message))) => error(status, message),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    if cors {
+        allow_origin(&mut response);
+    }
+    println!("{} {}", request, response.status());
+    Ok(response)
+}
+
+fn route(lib: &mut Library, method: &Method, path: &str, query: &str, body: &[u8]) -> ApiResult {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let params: Vec<(String, String)> = form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["entries"]) => {
+            let ids = match params.iter().find(|(k, _)| k == "q") {
+                Some((_, q)) => lib.search_ids(q),
+                None => (0..lib.entries.len()).collect(),
+            };
+            let entries = ids.into_iter().map(|i| entry_json(lib, i)).collect();
+            reply(StatusCode::OK, Value::Array(entries))
+        }
+        (&Method::GET, ["entries", id]) => {
+            let id = entry_id(lib, id)?;
+            reply(StatusCode::OK, entry_json(lib, id))
+        }
+        (&Method::POST, ["entries"]) => {
+            let new: NewEntry = parse_body(body)?;
+            new_citekey(lib, &new.name)?;
+            if let Some(i) = lib.find(&new.id).first() {
+                let message = format!("'{}' is already in library as entry {}", new.id, i);
+                return Err((StatusCode::CONFLICT, message));
+            }
+            let id = lib
+                .try_add(&new.name, &new.id)
+                .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+            save(lib)?;
+            reply(StatusCode::CREATED, entry_json(lib, id))
+        }
+        (&Method::DELETE, ["entries", id]) => {
+            let id = entry_id(lib, id)?;
+            let entry = entry_json(lib, id);
+            lib.del(id).map_err(|e| (StatusCode::NOT_FOUND, e))?;
+            save(lib)?;
+            reply(StatusCode::OK, entry)
+        }
+        (&Method::POST, ["entries", id, "link"]) => {
+            let id = entry_id(lib, id)?;
+            let link: Link = parse_body(body)?;
+            if !link.path.is_file() {
+                let message = format!("no such file {}", link.path.display());
+                return Err((StatusCode::BAD_REQUEST, message));
+            }
+            lib.link(id, link.path)
+                .map_err(|e| (StatusCode::NOT_FOUND, e))?;
+            save(lib)?;
+            reply(StatusCode::OK, entry_json(lib, id))
+        }
+        (&Method::POST, ["capture"]) => capture(lib, parse_body(body)?),
+        (&Method::GET, ["bib"]) => bibtex(lib, (0..lib.entries.len()).collect(), &params),
+        (&Method::GET, ["entries", id, "bib"]) => {
+            let id = entry_id(lib, id)?;
+            bibtex(lib, vec![id], &params)
+        }
+        _ => Err((
+            StatusCode::NOT_FOUND,
+            format!("no route {} {}", method, path),
+        )),
+    }
+}
+
+// query `?mode=biblatex&utf8` are options `--mode biblatex --utf8` of `gen`
+fn bibtex(lib: &Library, ids: Vec<usize>, params: &[(String, String)]) -> ApiResult {
+    let mut args = vec![String::from("bib")];
+    for (k, v) in params {
+        args.push(format!("--{}", k));
+        if !v.is_empty() {
+            args.push(v.clone());
+        }
+    }
+    let opts =
+        GenOptions::from_iter_safe(args).map_err(|e| (StatusCode::BAD_REQUEST, e.message))?;
+    let bibs = lib
+        .bibtex(&ids, &opts)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(Response::builder()
+        .header(header::CONTENT_TYPE, "application/x-bibtex; charset=utf-8")
+        .body(Body::from(bibs.join("\n")))
+        .unwrap())
+}
+
+// find identifiers of the page, add the entry and download its pdf file if asked,
+// a failed download is only a warning
+fn capture(lib: &mut Library, page: Capture) -> ApiResult {
+    if let Some(name) = &page.name {
+        new_citekey(lib, name)?;
+    }
+    let meta = downloader::citation_meta(&page.html);
+    let id = meta
+        .doi
+        .clone()
+        .or_else(|| meta.arxiv_id.clone())
+        .or_else(|| id_from_url(&page.url))
+        .ok_or_else(|| {
+            let message = format!("no DOI or arXiv id found on {}", page.url);
+            (StatusCode::UNPROCESSABLE_ENTITY, message)
+        })?;
+    if let Some(i) = lib.find(&id).first() {
+        let message = format!("'{}' is already in library as entry {}", id, i);
+        return Err((StatusCode::CONFLICT, message));
+    }
+    let i = lib
+        .try_add(page.name.as_deref().unwrap_or(&id), &id)
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e))?;
+    if page.name.is_none() {
+        let used: HashSet<String> = lib.entries[..i].iter().map(|e| e.name.clone()).collect();
+        let key = lib.entries[i]
+            .parsed()
+            .map(|b| lint::citekey(&b, config::get().citekey_template()))
+            .filter(|k| !k.is_empty())
+            .unwrap_or_else(|| id.replace('/', "_"));
+        let key = match used.contains(&key) {
+            true => lint::unique_citekey(&key, &used),
+            false => key,
+        };
+        lib.entries[i].rename(&key);
+    }
+    let pdf_url = match (&meta.pdf_url, &meta.arxiv_id) {
+        (Some(u), _) => reqwest::Url::parse(&page.url)
+            .and_then(|base| base.join(u))
+            .map(|u| u.to_string())
+            .ok(),
+        (None, Some(a)) => Some(ArxivDownloader::pdf_url(a)),
+        (None, None) if !id.starts_with("10.") => Some(ArxivDownloader::pdf_url(&id)),
+        (None, None) => None,
+    };
+    let warning = match pdf_url {
+        Some(url) if page.pdf => fetch_pdf(lib, i, &url).err(),
+        None if page.pdf => Some(String::from("no pdf file found on page")),
+        _ => None,
+    };
+    save(lib)?;
+    let mut entry = entry_json(lib, i);
+    if let (Some(w), Some(map)) = (warning, entry.as_object_mut()) {
+        map.insert(String::from("warning"), json!(w));
+    }
+    reply(StatusCode::CREATED, entry)
+}
+
+// citekeys sent by pages name files in the managed folder and must stay inside it
+fn check_citekey(name: &str) -> Result<(), String> {
+    let unsafe_char = |c: char| c == '/' || c == '\\' || c.is_whitespace() || c.is_control();
+    if name.is_empty() || name.contains("..") || name.contains(unsafe_char) {
+        return Err(format!("invalid citekey '{}'", name));
+    }
+    Ok(())
+}
+
+fn new_citekey(lib: &Library, name: &str) -> Result<(), (StatusCode, String)> {
+    check_citekey(name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if let Some(i) = lib.entries.iter().position(|e| e.name == name) {
+        let message = format!("citekey '{}' is already used by entry {}", name, i);
+        return Err((StatusCode::CONFLICT, message));
+    }
+    Ok(())
+}
+
+fn fetch_pdf(lib: &mut Library, i: usize, url: &str) -> Result<(), String> {
+    check_citekey(&lib.entries[i].name)?;
+    let path = lib
+        .managed_folder()
+        .join(format!("{}.pdf", lib.entries[i].name));
+    if path.exists() {
+        return Err(format!(
+            "{} exists, pdf file not downloaded",
+            path.display()
+        ));
+    }
+    let rt = Runtime::new().unwrap();
+    rt.block_on(downloader::download_file(
+        url,
+        &path,
+        &ProgressBar::hidden(),
+    ))
+    .map_err(|e| format!("download {} error: {}", url, e))?;
+    // publishers answer with a login page instead of the pdf file without access
+    if files::check_file(&path) != FileStatus::Ok {
+        std::fs::remove_file(&path).ok();
+        return Err(format!("{} is not a pdf file, login may be required", url));
+    }
+    lib.link(i, path)
+}
+
+// identifiers in page urls, e.g. https://arxiv.org/abs/1904.12901
+// or https://dl.acm.org/doi/abs/10.1145/2486001.2486012
+fn id_from_url(url: &str) -> Option<String> {
+    let url = url.split(['?', '#']).next()?;
+    for prefix in ["arxiv.org/abs/", "arxiv.org/pdf/"].iter() {
+        if let Some(i) = url.find(prefix) {
+            let id = url[i + prefix.len()..].trim_end_matches(".pdf");
+            return Some(id.trim_end_matches('/').to_owned()).filter(|id| !id.is_empty());
+        }
+    }
+    let doi = url.find("doi")?;
+    let start = doi + url[doi..].find("/10.")? + 1;
+    Some(url[start..].trim_end_matches('/').to_owned())
+}
+
+// the bookmarklet as a link to drag into the bookmarks bar, posting to this server
+fn bookmarklet(host: &str) -> Response<Body> {
+    let code: String = BOOKMARKLET
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.starts_with("//"))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace("127.0.0.1:8080", host);
+    let href = format!("javascript:{}", code)
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;");
+    let page = format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>refhelper</title></head><body>\n\
+         <p>Drag this link to your bookmarks bar, and click it on the page of a paper:</p>\n\
+         <p><a href=\"{}\">Add to refhelper</a></p>\n</body></html>\n",
+        href
+    );
+    Response::builder()
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(page))
+        .unwrap()
+}
+
+// Host of DNS names other than localhost may point to us by DNS rebinding, and
+// pages of other sites may post forms or text/plain bodies without preflight
+fn check_request(
+    method: &Method,
+    path: &str,
+    headers: &HeaderMap,
+    port: u16,
+) -> Result<(), (StatusCode, String)> {
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let host = header(header::HOST).unwrap_or("");
+    let allowed = host.rsplit_once(':').is_some_and(|(name, p)| {
+        let name = name.trim_start_matches('[').trim_end_matches(']');
+        p == port.to_string() && (name == "localhost" || name.parse::<IpAddr>().is_ok())
+    });
+    if !allowed {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("host '{}' not allowed", host),
+        ));
+    }
+    let reads = [Method::GET, Method::HEAD, Method::OPTIONS].contains(method);
+    if reads || path == "/capture" {
+        return Ok(());
+    }
+    if let Some(origin) = header(header::ORIGIN) {
+        if origin != format!("http://{}", host) {
+            let message = format!("origin '{}' not allowed", origin);
+            return Err((StatusCode::FORBIDDEN, message));
+        }
+    }
+    let json = header(header::CONTENT_TYPE).is_some_and(|t| t.starts_with("application/json"));
+    if method == Method::POST && !json {
+        let message = String::from("expect a body of application/json");
+        return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, message));
+    }
+    Ok(())
+}
+
+fn allow_origin(response: &mut Response<Body>) {
+    let headers = response.headers_mut();
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_ORIGIN,
+        header::HeaderValue::from_static("*"),
+    );
+    headers.insert(
+        header::ACCESS_CONTROL_ALLOW_HEADERS,
+        header::HeaderValue::from_static("content-type"),
+    );
+}
+
+fn entry_id(lib: &Library, id: &str) -> Result<usize, (StatusCode, String)> {
+    match id.parse::<usize>() {
+        Ok(i) if i < lib.entries.len() => Ok(i),
+        _ => Err((StatusCode::NOT_FOUND, format!("no entry {}", id))),
+    }
+}
+
+// an entry with its position, which later requests refer to
+fn entry_json(lib: &Library, id: usize) -> Value {
+    let mut value = serde_json::to_value(&lib.entries[id]).unwrap_or_default();
+    if let Some(map) = value.as_object_mut() {
+        map.insert(String::from("id"), json!(id));
+    }
+    value
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, (StatusCode, String)> {
+    serde_json::from_slice(body).map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))
+}
+
+fn save(lib: &mut Library) -> Result<(), (StatusCode, String)> {
+    lib.save().map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("save error: {}", e),
+        )
+    })
+}
+
+fn reply(status: StatusCode, value: Value) -> ApiResult {
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(value.to_string()))
+        .unwrap())
+}
+
+fn error(status: StatusCode, message: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "error": message }).to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::Entry;
+
+    #[test]
+    fn test_route() {
+        let mut lib = Library::default();
+        let mut entry = Entry::new("swan", "10.1145/2486001.2486012");
+        entry.bibtex = String::from("@article{swan, title = {Achieving high utilization with software-driven WAN}, year = {2013}}");
+        entry.title = String::from("Achieving high utilization with software-driven WAN");
+        lib.entries.push(entry);
+        lib.entries.push(Entry::new("rl", "1904.12901"));
+
+        let get =
+            |lib: &mut Library, path: &str, query: &str| route(lib, &Method::GET, path, query, b"");
+        let res = get(&mut lib, "/entries", "q=software").unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = get(&mut lib, "/entries/1/bib", "mode=biblatex").unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let err = get(&mut lib, "/bib", "mode=nope").unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        let err = get(&mut lib, "/entries/2", "").unwrap_err();
+        assert_eq!(err.0, StatusCode::NOT_FOUND);
+
+        let body = br#"{"name": "dup", "id": "1904.12901"}"#;
+        let err = route(&mut lib, &Method::POST, "/entries", "", body).unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let body = br#"{"name": "rl", "id": "2001.00001"}"#;
+        let err = route(&mut lib, &Method::POST, "/entries", "", body).unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let body = br#"{"url": "https://arxiv.org/abs/2001.00001", "html": "", "name": "../../x"}"#;
+        let err = route(&mut lib, &Method::POST, "/capture", "", body).unwrap_err();
+        assert_eq!(err.0, StatusCode::BAD_REQUEST);
+        let body = br#"{"url": "https://arxiv.org/abs/2001.00001", "html": "", "name": "swan"}"#;
+        let err = route(&mut lib, &Method::POST, "/capture", "", body).unwrap_err();
+        assert_eq!(err.0, StatusCode::CONFLICT);
+        let res = route(&mut lib, &Method::DELETE, "/entries/0", "", b"").unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(lib.entries.len(), 1);
+        assert_eq!(lib.entries[0].name, "rl");
+    }
+
+    #[test]
+    fn test_capture_ids() {
+        let html = r#"<html><head><meta name="citation_doi" content="doi:10.1145/2486001.2486012">
+            <meta name="citation_pdf_url" content="/doi/pdf/10.1145/2486001.2486012"></head></html>"#;
+        let meta = downloader::citation_meta(html);
+        assert_eq!(meta.doi.as_deref(), Some("10.1145/2486001.2486012"));
+        assert_eq!(meta.arxiv_id, None);
+        assert!(meta.pdf_url.is_some());
+        assert_eq!(
+            id_from_url("https://arxiv.org/pdf/1904.12901v2.pdf").as_deref(),
+            Some("1904.12901v2")
+        );
+        assert_eq!(
+            id_from_url("https://dl.acm.org/doi/abs/10.1145/2486001.2486012?x=1").as_deref(),
+            Some("10.1145/2486001.2486012")
+        );
+        assert_eq!(id_from_url("https://example.com/10.1/x"), None);
+        assert!(check_citekey("he2016deep").is_ok());
+        for name in &["", "a/b", "a\\b", "..", "a b", "x\n"] {
+            assert!(check_citekey(name).is_err(), "{:?}", name);
+        }
+    }
+
+    #[test]
+    fn test_check_request() {
+        let check = |method, path, pairs: &[(&'static str, &str)]| {
+            let mut headers = HeaderMap::new();
+            for (k, v) in pairs {
+                headers.insert(*k, v.parse().unwrap());
+            }
+            check_request(&method, path, &headers, 8080).map_err(|e| e.0)
+        };
+        let local = ("host", "127.0.0.1:8080");
+        let json = ("content-type", "application/json");
+        assert!(check(Method::GET, "/entries", &[local]).is_ok());
+        assert!(check(Method::GET, "/entries", &[("host", "[::1]:8080")]).is_ok());
+        assert!(check(
+            Method::POST,
+            "/entries",
+            &[("host", "localhost:8080"), json]
+        )
+        .is_ok());
+        // DNS rebinding
+        let rebound = ("host", "evil.example:8080");
+        assert_eq!(
+            check(Method::GET, "/bib", &[rebound]),
+            Err(StatusCode::FORBIDDEN)
+        );
+        // simple requests of other sites
+        let text = ("content-type", "text/plain");
+        assert_eq!(
+            check(Method::POST, "/entries", &[local, text]),
+            Err(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        let origin = ("origin", "https://evil.example");
+        assert_eq!(
+            check(Method::POST, "/entries/0/link", &[local, json, origin]),
+            Err(StatusCode::FORBIDDEN)
+        );
+        assert!(check(Method::POST, "/capture", &[local, text, origin]).is_ok());
+    }
+}
@@ -0,0 +1,469 @@
+// storage backends of a library, chosen by file extension:
+// `.db`, `.sqlite` and `.sqlite3` are SQLite databases, others are JSON files
+use rusqlite::{params, Connection, OpenFlags};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::Entry;
+
+const SQLITE_EXTENSIONS: &[&str] = &["db", "sqlite", "sqlite3"];
+
+pub trait Storage: Send {
+    fn load(&mut self) -> Result<Vec<Entry>, Box<dyn Error>>;
+
+    fn save(&mut self, entries: &[Entry]) -> Result<(), Box<dyn Error>>;
+
+    // positions of saved entries with this DOI or citekey, None without an index
+    fn lookup(&self, _doi_or_key: &str) -> Result<Option<Vec<usize>>, Box<dyn Error>> {
+        Ok(None)
+    }
+}
+
+fn is_sqlite(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    SQLITE_EXTENSIONS.contains(&ext.to_lowercase().as_str())
+}
+
+pub fn open(path: &Path) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+    if is_sqlite(path) {
+        Ok(Box::new(SqliteStorage::open(path)?))
+    } else {
+        Ok(Box::new(JsonStorage {
+            path: path.to_path_buf(),
+            version: SCHEMA_VERSION,
+            extra: Map::new(),
+        }))
+    }
+}
+
+// entries of another library without changing it, e.g. for `diff` and `merge`,
+// libraries of older versions are migrated in memory only
+pub fn read_entries(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    if is_sqlite(path) {
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+        let (_, rows) = read_rows(&conn)?;
+        return Ok(rows.into_iter().map(|(_, _, e)| e).collect());
+    }
+    let lib = read_json(path)?;
+    let entries = lib.entries.into_iter().map(serde_json::from_value);
+    Ok(entries.collect::<Result<Vec<Entry>, _>>()?)
+}
+
+// the whole library in one JSON file, rewritten on every save,
+// pretty printed with one field per line so that it diffs and merges well in git
+pub struct JsonStorage {
+    path: PathBuf,
+    version: u32,
+    extra: Map<String, Value>, // unknown fields of library, kept on save
+}
+
+#[derive(Serialize)]
+struct JsonLibrary<'a> {
+    schema_version: u32,
+    entries: &'a [Entry],
+    #[serde(flatten)]
+    extra: &'a Map<String, Value>,
+}
+
+// content of a JSON library, entries are migrated to current version
+pub struct RawLibrary {
+    pub version: u32,
+    pub entries: Vec<Value>,
+    pub extra: Map<String, Value>,
+}
+
+pub fn read_json(path: &Path) -> Result<RawLibrary, Box<dyn Error>> {
+    let file = File::open(path)?;
+    // a newly created library is empty
+    if file.metadata()?.len() == 0 {
+        return Ok(RawLibrary {
+            version: SCHEMA_VERSION,
+            entries: vec![],
+            extra: Map::new(),
+        });
+    }
+    read_json_from(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+pub fn read_json_from<R: Read>(reader: R) -> Result<RawLibrary, Box<dyn Error>> {
+    let mut lib: Map<String, Value> = serde_json::from_reader(reader)?;
+    let version = lib
+        .remove("schema_version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(1) as u32;
+    let mut entries = match lib.remove("entries") {
+        Some(Value::Array(entries)) => entries,
+        _ => return Err("library has no entries".into()),
+    };
+    entries
+        .iter_mut()
+        .for_each(|e| schema::migrate_entry(e, version));
+    Ok(RawLibrary {
+        version,
+        entries,
+        extra: lib,
+    })
+}
+
+pub fn write_json(
+    path: &Path,
+    version: u32,
+    entries: &[Entry],
+    extra: &Map<String, Value>,
+) -> Result<(), Box<dyn Error>> {
+    write_json_to(BufWriter::new(File::create(path)?), version, entries, extra)
+}
+
+pub fn write_json_to<W: Write>(
+    mut writer: W,
+    version: u32,
+    entries: &[Entry],
+    extra: &Map<String, Value>,
+) -> Result<(), Box<dyn Error>> {
+    // entries keep library order, so that their ids are the same when loaded again
+    let lib = JsonLibrary {
+        schema_version: version,
+        entries,
+        extra,
+    };
+    serde_json::to_writer_pretty(&mut writer, &lib)?;
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+impl Storage for JsonStorage {
+    fn load(&mut self) -> Result<Vec<Entry>, Box<dyn Error>> {
+        if !self.path.exists() {
+            File::create(&self.path)?;
+            return Ok(vec![]);
+        }
+        let lib = read_json(&self.path)?;
+        check_version(lib.version, &self.path)?;
+        self.extra = lib.extra;
+        self.version = lib.version.max(SCHEMA_VERSION);
+        let entries = lib
+            .entries
+            .into_iter()
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<Entry>, _>>()?;
+        if lib.version < SCHEMA_VERSION {
+            self.save(&entries)?;
+        }
+        Ok(entries)
+    }
+
+    fn save(&mut self, entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+        write_json(&self.path, self.version, entries, &self.extra)
+    }
+}
+
+// back up libraries of older versions before they are migrated
+fn check_version(version: u32, path: &Path) -> Result<(), Box<dyn Error>> {
+    if version < SCHEMA_VERSION {
+        let backup = schema::backup(path, version)?;
+        eprintln!(
+            "migrate library from version {} to {}, backup at {}",
+            version,
+            SCHEMA_VERSION,
+            backup.display()
+        );
+    } else if version > SCHEMA_VERSION {
+        eprintln!(
+            "library version {} is newer than {}, unknown fields are kept as is",
+            version, SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+// one row per entry, only changed entries are written on save
+pub struct SqliteStorage {
+    conn: Connection,
+    path: PathBuf,
+    rows: Vec<SavedRow>,            // in library order
+    positions: HashMap<i64, usize>, // row id -> position in library
+}
+
+// row id, sort key and entry as last written, None if the row has to be rewritten;
+// sort keys only increase along the library and may have gaps, so that removing
+// or appending entries leaves other rows alone
+type SavedRow = (i64, i64, Option<Entry>);
+
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<SqliteStorage, Box<dyn Error>> {
+        SqliteStorage::init(Connection::open(path)?, path)
+    }
+
+    fn init(conn: Connection, path: &Path) -> Result<SqliteStorage, Box<dyn Error>> {
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'entries'",
+            [],
+            |r| r.get(0),
+        )?;
+        if !exists {
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS entries (
+                id INTEGER PRIMARY KEY,
+                pos INTEGER NOT NULL,
+                citekey TEXT NOT NULL,
+                doi TEXT NOT NULL,
+                hash TEXT NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS entries_citekey ON entries(citekey);
+            CREATE INDEX IF NOT EXISTS entries_doi ON entries(doi);",
+        )?;
+        Ok(SqliteStorage {
+            conn,
+            path: path.to_path_buf(),
+            rows: vec![],
+            positions: HashMap::new(),
+        })
+    }
+}
+
+// row id, sort key and entry
+type Row = (i64, i64, Entry);
+
+// schema version, and rows in library order
+fn read_rows(conn: &Connection) -> Result<(u32, Vec<Row>), Box<dyn Error>> {
+    // databases of first version had no user_version
+    let version: u32 = conn.query_row("PRAGMA user_version", [], |r| r.get(0))?;
+    let version = version.max(1);
+    let mut stmt = conn.prepare("SELECT id, pos, data FROM entries ORDER BY pos, id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, i64>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let (id, pos, data) = row?;
+        let mut value: Value = serde_json::from_str(&data)?;
+        schema::migrate_entry(&mut value, version);
+        entries.push((id, pos, serde_json::from_value(value)?));
+    }
+    Ok((version, entries))
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Vec<Entry>, Box<dyn Error>> {
+        let (version, rows) = read_rows(&self.conn)?;
+        check_version(version, &self.path)?;
+        // rows of migrated entries are rewritten
+        let migrated = version < SCHEMA_VERSION;
+        let mut entries = Vec::with_capacity(rows.len());
+        self.rows.clear();
+        for (id, pos, entry) in rows {
+            self.rows
+                .push((id, pos, Some(entry.clone()).filter(|_| !migrated)));
+            entries.push(entry);
+        }
+        self.index_rows();
+        if migrated {
+            self.save(&entries)?;
+            self.conn
+                .pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(entries)
+    }
+
+    fn save(&mut self, entries: &[Entry]) -> Result<(), Box<dyn Error>> {
+        // saved rows are matched by uid, copies of an entry in order
+        let mut saved: HashMap<&str, Vec<usize>> = HashMap::new();
+        for (i, (_, _, entry)) in self.rows.iter().enumerate().rev() {
+            let uid = entry.as_ref().map_or("", |e| e.uid.as_str());
+            saved.entry(uid).or_default().push(i);
+        }
+        let tx = self.conn.transaction()?;
+        // saved row if any, row id, sort key, and whether the entry was written
+        let mut plan: Vec<(Option<usize>, i64, i64, bool)> = Vec::with_capacity(entries.len());
+        let mut last = -1;
+        for entry in entries {
+            let old = saved.get_mut(entry.uid.as_str()).and_then(|v| v.pop());
+            let (id, pos, written) = match old {
+                Some(i) => {
+                    let (id, old_pos, saved_entry) = &self.rows[i];
+                    let pos = if *old_pos > last { *old_pos } else { last + 1 };
+                    let written = saved_entry.as_ref() != Some(entry);
+                    if written {
+                        let data = serde_json::to_string(entry)?;
+                        let hash = hex::encode(Sha256::digest(data.as_bytes()));
+                        tx.execute(
+                            "UPDATE entries SET pos = ?1, citekey = ?2, doi = ?3, hash = ?4, data = ?5 WHERE id = ?6",
+                            params![pos, entry.name, entry.doi, hash, data, id],
+                        )?;
+                    } else if pos != *old_pos {
+                        tx.execute(
+                            "UPDATE entries SET pos = ?1 WHERE id = ?2",
+                            params![pos, id],
+                        )?;
+                    }
+                    (*id, pos, written)
+                }
+                None => {
+                    let data = serde_json::to_string(entry)?;
+                    let hash = hex::encode(Sha256::digest(data.as_bytes()));
+                    tx.execute(
+                        "INSERT INTO entries (pos, citekey, doi, hash, data) VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![last + 1, entry.name, entry.doi, hash, data],
+                    )?;
+                    (tx.last_insert_rowid(), last + 1, true)
+                }
+            };
+            plan.push((old, id, pos, written));
+            last = pos;
+        }
+        for &i in saved.values().flatten() {
+            tx.execute("DELETE FROM entries WHERE id = ?1", params![self.rows[i].0])?;
+        }
+        tx.commit()?;
+        // unchanged entries keep their saved copies
+        let mut old: Vec<Option<SavedRow>> = std::mem::take(&mut self.rows)
+            .into_iter()
+            .map(Some)
+            .collect();
+        for ((i, id, pos, written), entry) in plan.into_iter().zip(entries) {
+            let saved_entry = match (written, i) {
+                (false, Some(i)) => old[i].take().and_then(|(_, _, e)| e),
+                _ => Some(entry.clone()),
+            };
+            self.rows.push((id, pos, saved_entry));
+        }
+        self.index_rows();
+        Ok(())
+    }
+
+    // saved entries with this DOI or citekey, from indexed columns
+    fn lookup(&self, doi_or_key: &str) -> Result<Option<Vec<usize>>, Box<dyn Error>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT id FROM entries WHERE citekey = ?1 OR doi = ?1")?;
+        let ids = stmt.query_map([doi_or_key], |r| r.get::<_, i64>(0))?;
+        let mut found = Vec::new();
+        for id in ids {
+            found.extend(self.positions.get(&id?));
+        }
+        found.sort_unstable();
+        Ok(Some(found))
+    }
+}
+
+impl SqliteStorage {
+    fn index_rows(&mut self) {
+        self.positions = self
+            .rows
+            .iter()
+            .enumerate()
+            .map(|(i, r)| (r.0, i))
+            .collect();
+    }
+}
+
+// copy a library to a new file, possibly of another format,
+// text extracted from pdf files is copied as well
+pub fn migrate(from: &Path, to: &Path) -> Result<usize, Box<dyn Error>> {
+    if to.exists() && fs::metadata(to)?.len() > 0 {
+        return Err(format!("{} already exists", to.display()).into());
+    }
+    let entries = read_entries(from)?;
+    open(to)?.save(&entries)?;
+    let fulltext = from.with_extension("fulltext.json");
+    if fulltext.exists() {
+        fs::copy(fulltext, to.with_extension("fulltext.json"))?;
+    }
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_json_order() -> Result<(), Box<dyn Error>> {
+        let entries = vec![
+            Entry::new("test1", "1904.12901"),
+            Entry::new("swan", "10.1145/2486001.2486012"),
+        ];
+        let mut json = Vec::new();
+        write_json_to(&mut json, SCHEMA_VERSION, &entries, &Map::new())?;
+        let lib = read_json_from(&json[..])?;
+        assert_eq!(lib.entries[0]["name"], "test1");
+        assert_eq!(lib.entries[1]["name"], "swan");
+        // the same library is written the same way
+        let mut again = Vec::new();
+        write_json_to(&mut again, SCHEMA_VERSION, &entries, &Map::new())?;
+        assert_eq!(json, again);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_entries() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("refhelper-v1-{}.json", std::process::id()));
+        let json = r#"{"entries": [{"name": "swan", "doi": "10.1145/2486001.2486012"}]}"#;
+        fs::write(&path, json)?;
+        let entries = read_entries(&path)?;
+        // migrated in memory, the file is left as is without backup
+        assert!(!entries[0].uid.is_empty());
+        assert_eq!(fs::read_to_string(&path)?, json);
+        assert!(!path.with_extension("json.v1.bak").exists());
+        fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage() -> Result<(), Box<dyn Error>> {
+        let mut storage = SqliteStorage::init(Connection::open_in_memory()?, Path::new(""))?;
+        let entries = vec![
+            Entry::new("swan", "10.1145/2486001.2486012"),
+            Entry::new("test1", "1904.12901"),
+            Entry::new("test2", "2109.03989"),
+        ];
+        storage.save(&entries)?;
+        let row = storage.rows[2].0;
+        // remove the first entry, other rows are left alone
+        let mut entries: Vec<Entry> = entries.into_iter().skip(1).collect();
+        storage.save(&entries)?;
+        assert_eq!(storage.rows[1].0, row);
+        let pos = |storage: &SqliteStorage, doi: &str| {
+            storage
+                .conn
+                .query_row("SELECT pos FROM entries WHERE doi = ?1", [doi], |r| {
+                    r.get::<_, i64>(0)
+                })
+                .ok()
+        };
+        assert_eq!(pos(&storage, "2109.03989"), Some(2));
+        assert_eq!(pos(&storage, "10.1145/2486001.2486012"), None);
+        assert_eq!(storage.lookup("2109.03989")?, Some(vec![1]));
+        assert_eq!(storage.lookup("test1")?, Some(vec![0]));
+        // a changed entry keeps its row, an appended one goes after the last
+        entries[0].rename("rl");
+        entries.push(Entry::new("swan", "10.1145/2486001.2486012"));
+        storage.save(&entries)?;
+        assert_eq!(storage.lookup("test1")?, Some(vec![]));
+        assert_eq!(storage.lookup("rl")?, Some(vec![0]));
+        assert_eq!(pos(&storage, "10.1145/2486001.2486012"), Some(3));
+        entries.pop();
+        storage.save(&entries)?;
+        let loaded = storage.load()?;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].name, "rl");
+        let count: i64 = storage
+            .conn
+            .query_row("SELECT COUNT(*) FROM entries", [], |r| r.get(0))?;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,513 @@
+// full-screen terminal UI: a scrollable list of entries with live search,
+// a detail pane of the selected one, and keys to view, link, tag, delete and download
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use indicatif::ProgressBar;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use crate::{downloader, utils, Library};
+
+const TICK: Duration = Duration::from_millis(200);
+const NAME_WIDTH: usize = 20;
+// side by side panes when terminal is at least this wide, stacked otherwise
+const WIDE: usize = 100;
+const HELP: &str =
+    "/ search  enter view  l link  t tag  x delete  d download  D download all  q quit";
+
+// raw mode and alternate screen, restored when dropped even on errors
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Screen, Box<dyn Error>> {
+        terminal::enable_raw_mode()?;
+        execute!(io::stdout(), EnterAlternateScreen, Hide)?;
+        Ok(Screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(io::stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+enum Action {
+    Link,
+    Tag,
+    Delete,
+}
+
+enum Mode {
+    Normal,
+    Search,
+    Prompt(Action, String),
+}
+
+// downloads running in another thread, progress bars are hidden and read when drawing
+struct Download {
+    bars: Vec<(String, ProgressBar)>,
+    folder: PathBuf,
+    done: Receiver<Vec<Result<(), String>>>,
+}
+
+struct App {
+    lib: Library,
+    // ids of entries shown in list, all or the search results
+    ids: Vec<usize>,
+    selected: usize,
+    offset: usize,
+    query: String,
+    mode: Mode,
+    status: String,
+    download: Option<Download>,
+}
+
+pub fn run(lib: Library) -> Result<(), Box<dyn Error>> {
+    let mut app = App {
+        ids: (0..lib.entries.len()).collect(),
+        lib,
+        selected: 0,
+        offset: 0,
+        query: String::new(),
+        mode: Mode::Normal,
+        status: String::from(HELP),
+        download: None,
+    };
+    let screen = Screen::enter()?;
+    let mut out = io::stdout();
+    loop {
+        app.poll_download();
+        app.draw(&mut out)?;
+        if !event::poll(TICK)? {
+            continue;
+        }
+        match event::read()? {
+            Event::Key(key) if !app.on_key(key) => break,
+            Event::Resize(_, _) => queue!(out, Clear(ClearType::All))?,
+            _ => {}
+        }
+    }
+    drop(screen);
+    app.lib.save()
+}
+
+impl App {
+    fn selected_id(&self) -> Option<usize> {
+        self.ids.get(self.selected).copied()
+    }
+
+    fn refresh(&mut self) {
+        self.ids = if self.query.trim().is_empty() {
+            (0..self.lib.entries.len()).collect()
+        } else {
+            self.lib.search_ids(&self.query)
+        };
+        self.selected = self.selected.min(self.ids.len().saturating_sub(1));
+    }
+
+    fn save(&mut self, done: String) {
+        self.status = match self.lib.save() {
+            Ok(()) => done,
+            Err(e) => format!("save library failed, error {}", e),
+        };
+    }
+
+    fn select(&mut self, to: isize) {
+        let last = self.ids.len().saturating_sub(1) as isize;
+        self.selected = to.max(0).min(last) as usize;
+    }
+
+    // returns false to quit
+    fn on_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return false;
+        }
+        match std::mem::replace(&mut self.mode, Mode::Normal) {
+            Mode::Normal => return self.on_normal_key(key),
+            Mode::Search => match key.code {
+                KeyCode::Esc => {
+                    self.query.clear();
+                    self.refresh();
+                }
+                KeyCode::Enter => {}
+                code => {
+                    edit_line(&mut self.query, code);
+                    self.refresh();
+                    self.mode = Mode::Search;
+                }
+            },
+            Mode::Prompt(Action::Delete, _) => match key.code {
+                KeyCode::Char('y') => self.delete(),
+                _ => self.status = String::from("not deleted"),
+            },
+            Mode::Prompt(action, mut line) => match key.code {
+                KeyCode::Esc => self.status = String::new(),
+                KeyCode::Enter => match action {
+                    Action::Link => self.link(line.trim()),
+                    _ => self.tag(&line),
+                },
+                code => {
+                    edit_line(&mut line, code);
+                    self.mode = Mode::Prompt(action, line);
+                }
+            },
+        }
+        true
+    }
+
+    fn on_normal_key(&mut self, key: KeyEvent) -> bool {
+        let page = terminal::size().map_or(10, |(_, h)| h as isize / 2);
+        let selected = self.selected as isize;
+        match key.code {
+            KeyCode::Char('q') => return false,
+            KeyCode::Char('j') | KeyCode::Down => self.select(selected + 1),
+            KeyCode::Char('k') | KeyCode::Up => self.select(selected - 1),
+            KeyCode::PageDown => self.select(selected + page),
+            KeyCode::PageUp => self.select(selected - page),
+            KeyCode::Char('g') | KeyCode::Home => self.select(0),
+            KeyCode::Char('G') | KeyCode::End => self.select(isize::MAX),
+            KeyCode::Char('/') => self.mode = Mode::Search,
+            KeyCode::Esc => {
+                self.query.clear();
+                self.refresh();
+            }
+            KeyCode::Char('d') => self.download(false),
+            KeyCode::Char('D') => self.download(true),
+            code => {
+                let entry = match self.selected_id() {
+                    Some(id) => &self.lib.entries[id],
+                    None => return true,
+                };
+                match code {
+                    KeyCode::Enter | KeyCode::Char('v') => self.view(),
+                    KeyCode::Char('l') => self.mode = Mode::Prompt(Action::Link, String::new()),
+                    KeyCode::Char('t') => {
+                        self.mode = Mode::Prompt(Action::Tag, entry.tags().join(", "))
+                    }
+                    KeyCode::Char('x') => {
+                        let question = format!("delete {}? (y/n)", entry.name);
+                        self.mode = Mode::Prompt(Action::Delete, question);
+                    }
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    fn view(&mut self) {
+        let entry = &self.lib.entries[self.selected_id().unwrap()];
+        let target = match entry.pdf() {
+            Some(p) => p.display().to_string(),
+            None => match entry.attachments.first() {
+                Some(a) => a.location(),
+                None => return self.status = String::from("No pdf file of this entry"),
+            },
+        };
+        self.status = match utils::view_file(&target) {
+            Ok(()) => format!("opened {}", target),
+            Err(e) => format!("View file failed, error {}", e),
+        };
+    }
+
+    fn link(&mut self, path: &str) {
+        let path = PathBuf::from(path);
+        if !path.is_file() {
+            return self.status = format!("{} is not a file", path.display());
+        }
+        let id = self.selected_id().unwrap();
+        self.lib.entries[id].link(path.canonicalize().unwrap_or(path));
+        let mut done = format!("linked {}", self.lib.entries[id].name);
+        if let Some(e) = self.lib.update_fulltext_quietly().first() {
+            done = format!("{}, {}", done, e);
+        }
+        self.save(done);
+    }
+
+    fn tag(&mut self, line: &str) {
+        let tags: Vec<String> = line
+            .split(',')
+            .map(|t| t.trim().to_owned())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let id = self.selected_id().unwrap();
+        let entry = &mut self.lib.entries[id];
+        match entry.set_tags(&tags) {
+            Ok(()) => {
+                let done = format!("tagged {}", entry.name);
+                self.save(done);
+            }
+            Err(e) => self.status = e,
+        }
+    }
+
+    fn delete(&mut self) {
+        let id = self.selected_id().unwrap();
+        let name = self.lib.entries[id].name.clone();
+        if let Err(e) = self.lib.del(id) {
+            return self.status = e;
+        }
+        self.refresh();
+        self.save(format!("deleted {}", name));
+    }
+
+    fn download(&mut self, all: bool) {
+        if self.download.is_some() {
+            return self.status = String::from("downloads are still running");
+        }
+        let ids: Vec<String> = match self.selected_id() {
+            Some(id) if !all => vec![id],
+            _ if all => (0..self.lib.entries.len()).collect(),
+            _ => vec![],
+        }
+        .into_iter()
+        .map(|id| &self.lib.entries[id])
+        .filter(|e| e.pdf().is_none())
+        .map(|e| e.doi.clone())
+        .collect();
+        if ids.is_empty() {
+            return self.status = String::from("no entry without pdf file");
+        }
+        let bars: Vec<_> = ids
+            .iter()
+            .map(|id| (id.clone(), ProgressBar::hidden()))
+            .collect();
+        let pbs: Vec<_> = bars.iter().map(|(_, pb)| pb.clone()).collect();
+        let folder = self.lib.managed_folder();
+        let path = folder.clone();
+        let (tx, done) = mpsc::channel();
+        thread::spawn(move || {
+            let rt = Runtime::new().unwrap();
+            let ids: Vec<&str> = ids.iter().map(|id| &id[..]).collect();
+            let total = ProgressBar::hidden();
+            let results = rt.block_on(downloader::download_with(&ids, &path, &pbs, &total));
+            let _ = tx.send(results);
+        });
+        self.status = format!("downloading {} files into {}", bars.len(), folder.display());
+        self.download = Some(Download { bars, folder, done });
+    }
+
+    fn poll_download(&mut self) {
+        let results = match self.download.as_ref().map(|d| d.done.try_recv()) {
+            Some(Ok(results)) => results,
+            Some(Err(mpsc::TryRecvError::Disconnected)) => vec![Err(String::from("panicked"))],
+            _ => return,
+        };
+        let download = self.download.take().unwrap();
+        self.lib.link_downloaded(&download.folder);
+        let errors = self.lib.update_fulltext_quietly();
+        let failed: Vec<_> = download
+            .bars
+            .iter()
+            .zip(&results)
+            .filter_map(|((id, _), r)| r.as_ref().err().map(|e| format!("'{}' {}", id, e)))
+            .collect();
+        let mut done = format!(
+            "downloaded {} of {} files",
+            results.len() - failed.len(),
+            results.len()
+        );
+        if !failed.is_empty() {
+            done = format!("{}, failed: {}", done, failed.join(", "));
+        }
+        if !errors.is_empty() {
+            done = format!("{}, {}", done, errors.join(", "));
+        }
+        self.save(done);
+    }
+
+    // inline progress of an entry being downloaded
+    fn progress(&self, doi: &str) -> Option<String> {
+        let (_, pb) = self
+            .download
+            .as_ref()?
+            .bars
+            .iter()
+            .find(|(id, _)| id == doi)?;
+        Some(match pb.length() {
+            _ if pb.is_finished() => String::from("[done] "),
+            0 => String::from("[...] "),
+            len => format!("[{:>3}%] ", pb.position() * 100 / len),
+        })
+    }
+
+    fn list_lines(&self, width: usize, height: usize) -> Vec<(String, bool)> {
+        self.ids
+            .iter()
+            .enumerate()
+            .skip(self.offset)
+            .take(height)
+            .map(|(i, &id)| {
+                let e = &self.lib.entries[id];
+                let pdf = if e.pdf().is_some() { '*' } else { ' ' };
+                let progress = self.progress(&e.doi).unwrap_or_default();
+                let line = format!(
+                    "{:>4}{} {} {}{}",
+                    id,
+                    pdf,
+                    fit(&e.name, NAME_WIDTH),
+                    progress,
+                    e.title
+                );
+                (fit(&line, width), i == self.selected)
+            })
+            .collect()
+    }
+
+    fn detail_lines(&self, width: usize) -> Vec<String> {
+        let entry = match self.selected_id() {
+            Some(id) => &self.lib.entries[id],
+            None => return vec![],
+        };
+        let lines = entry.details();
+        lines.iter().flat_map(|l| wrap(l, width)).collect()
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> Result<(), Box<dyn Error>> {
+        let (w, h) = terminal::size()?;
+        let (w, h) = (w as usize, h as usize);
+        if h < 4 {
+            return Ok(());
+        }
+        let wide = w >= WIDE;
+        // rows between header and status line
+        let body = h - 2;
+        let (list_w, list_h) = if wide { (w / 2, body) } else { (w, body / 2) };
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + list_h {
+            self.offset = self.selected + 1 - list_h;
+        }
+
+        let path = self.lib.path.as_ref().map(|p| p.display().to_string());
+        let header = format!(
+            " refhelper: {}  {} of {} entries{}",
+            path.unwrap_or_default(),
+            self.ids.len(),
+            self.lib.entries.len(),
+            match &self.query[..] {
+                "" => String::new(),
+                q => format!(", search: {}", q),
+            }
+        );
+        queue!(out, MoveTo(0, 0), SetAttribute(Attribute::Reverse))?;
+        queue!(out, Print(fit(&header, w)), SetAttribute(Attribute::Reset))?;
+
+        let mut list = self.list_lines(list_w, list_h);
+        list.resize(list_h, (" ".repeat(list_w), false));
+        for (row, (line, selected)) in list.iter().enumerate() {
+            queue!(out, MoveTo(0, row as u16 + 1))?;
+            if *selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(out, Print(line), SetAttribute(Attribute::Reset))?;
+        }
+
+        // detail pane right of list with a `│` border, or below it with a `─` border
+        let (x, y, detail_w, detail_h) = if wide {
+            (list_w, 1, w - list_w, body)
+        } else {
+            queue!(out, MoveTo(0, list_h as u16 + 1), Print("─".repeat(w)))?;
+            (0, list_h + 2, w, body - list_h - 1)
+        };
+        let mut detail = self.detail_lines(detail_w.saturating_sub(2));
+        detail.resize(detail_h, String::new());
+        for (row, line) in detail.iter().enumerate() {
+            let border = if wide { "│ " } else { "" };
+            let line = fit(&format!("{}{}", border, line), detail_w);
+            queue!(out, MoveTo(x as u16, (y + row) as u16), Print(line))?;
+        }
+
+        let status = match &self.mode {
+            Mode::Normal => self.status.clone(),
+            Mode::Search => format!("/{}", self.query),
+            Mode::Prompt(Action::Link, line) => format!("link pdf file: {}", line),
+            Mode::Prompt(Action::Tag, line) => format!("tags (comma separated): {}", line),
+            Mode::Prompt(Action::Delete, question) => question.clone(),
+        };
+        queue!(out, MoveTo(0, h as u16 - 1), Print(fit(&status, w)))?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+fn edit_line(line: &mut String, code: KeyCode) {
+    match code {
+        KeyCode::Backspace => {
+            line.pop();
+        }
+        KeyCode::Char(c) => line.push(c),
+        _ => {}
+    }
+}
+
+// cut or pad text to exactly `width` columns
+fn fit(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in text.chars().map(|c| if c.is_control() { ' ' } else { c }) {
+        let w = c.width().unwrap_or(0);
+        if used + w > width {
+            break;
+        }
+        out.push(c);
+        used += w;
+    }
+    out.push_str(&" ".repeat(width - used));
+    out
+}
+
+// break text into lines of at most `width` columns, at spaces if possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = vec![];
+    // keep indent of the first line
+    let rest = text.trim_start_matches(' ');
+    let mut line = " ".repeat(text.len() - rest.len());
+    for word in rest.split(' ') {
+        let sep = if line.trim().is_empty() { 0 } else { 1 };
+        if sep == 1 && line.width() + sep + word.width() > width {
+            lines.push(std::mem::take(&mut line));
+        } else if sep == 1 {
+            line.push(' ');
+        }
+        // words longer than a line are split anywhere
+        for c in word.chars() {
+            if line.width() + c.width().unwrap_or(0) > width && !line.trim().is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            line.push(c);
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_fit_wrap() {
+        assert_eq!(fit("abc", 5), "abc  ");
+        assert_eq!(fit("论文abc", 3), "论 ");
+        assert_eq!(
+            wrap("a quick brown fox", 7),
+            vec!["a quick", "brown", "fox"]
+        );
+        assert_eq!(wrap("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(wrap("", 3), vec![""]);
+        assert_eq!(wrap("  pdf: a.pdf", 8), vec!["  pdf:", "a.pdf"]);
+    }
+}
@@ -1,52 +1,49 @@
 use biblatex::{self, Bibliography, ChunksExt};
 use comfy_table::{ContentArrangement, Table};
-use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
+use crossterm::terminal;
 use indicatif::ProgressIterator;
+use std::collections::hash_map::RandomState;
+use std::env;
 use std::error::Error;
+use std::fs;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::{self, Stdio};
 
+use crate::config;
+use crate::fulltext::TextMatch;
 use crate::Entry;
 
-pub fn read_doi_file(path: PathBuf) -> Vec<Entry> {
+pub fn read_doi_file(path: PathBuf) -> Result<Vec<Entry>, String> {
     // file format example:
     // name1 doi1 [pdf filepath]
     // name2 doi2 [pdf filepath]
     // ...   ...
     let mut entries: Vec<Entry> = Vec::new();
-    let content = match std::fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Read file failed, error: {}", e);
-            String::new()
-        }
-    };
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Read file failed, error: {}", e))?;
     for (lineno, line) in content.lines().enumerate() {
-        let words: Vec<&str> = line.trim().split_whitespace().collect();
+        let words: Vec<&str> = line.split_whitespace().collect();
         match words.len() {
+            0 => {}
             2 => entries.push(Entry::new(words[0], words[1])),
             3 => {
                 let mut entry = Entry::new(words[0], words[1]);
                 entry.link(PathBuf::from(words[2]));
                 entries.push(entry);
             }
-            _ => println!("error read doi file at line {}", lineno),
+            _ => return Err(format!("error read doi file at line {}", lineno + 1)),
         };
     }
-    entries
+    Ok(entries)
 }
 
-pub fn read_bibtex_file(path: PathBuf) -> Vec<Entry> {
+pub fn read_bibtex_file(path: PathBuf) -> Result<Vec<Entry>, String> {
     let mut entries: Vec<Entry> = Vec::new();
-    let content = match std::fs::read_to_string(path) {
-        Ok(c) => c,
-        Err(e) => {
-            eprintln!("Read file failed, error: {}", e);
-            String::new()
-        }
-    };
-    let bibs = Bibliography::parse(&content).unwrap();
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Read file failed, error: {}", e))?;
+    let bibs = Bibliography::parse(&content).ok_or("Invalid bibtex, syntax error")?;
     for e in bibs.into_iter().progress() {
         let name = &e.key;
         let arxiv_id = e.eprint().unwrap_or_default();
@@ -57,60 +54,155 @@ pub fn read_bibtex_file(path: PathBuf) -> Vec<Entry> {
         entry.title = e.title().unwrap_or_default().format_sentence();
         entries.push(entry);
     }
-    entries
+    Ok(entries)
 }
 
-pub fn view_pdf_file(path: &Path) -> Result<(), Box<dyn Error>> {
+// a long field value on one line, e.g. an abstract
+pub fn shorten(text: &str) -> String {
+    const MAX_LEN: usize = 60;
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    match text.char_indices().nth(MAX_LEN) {
+        Some((i, _)) => format!("{}...", &text[..i]),
+        None => text,
+    }
+}
+
+// open a file or url in external viewer, `viewer` of config if set
+pub fn view_file(target: &str) -> Result<(), Box<dyn Error>> {
+    if let Some(viewer) = &config::get().viewer {
+        let mut args = viewer.split_whitespace();
+        let program = args.next().ok_or("viewer of config is empty")?;
+        process::Command::new(program)
+            .args(args)
+            .arg(target)
+            .stdout(Stdio::null())
+            .spawn()?;
+        return Ok(());
+    }
+
     #[cfg(target_os = "linux")]
     process::Command::new("xdg-open")
-        .arg(path.as_os_str())
+        .arg(target)
         .stdout(Stdio::null())
         .spawn()?;
 
+    // not `cmd /C start`, which splits urls at `&` and takes a quoted target as window title
     #[cfg(target_os = "windows")]
-    process::Command::new("cmd")
-        .args(["/C", "start"])
-        .arg(path.as_os_str())
+    process::Command::new("explorer")
+        .arg(target)
         .stdout(Stdio::null())
         .spawn()?;
 
     Ok(())
 }
 
-pub fn print_entries(entries: &mut dyn Iterator<Item = (usize, &Entry)>) {
+// a fresh dir of our own in the temp dir, so that its files cannot be swapped by other users,
+// names taken by others or left by crashed runs are skipped
+pub fn create_temp_dir(prefix: &str) -> io::Result<PathBuf> {
+    for _ in 0..16 {
+        let suffix = RandomState::new().build_hasher().finish();
+        let name = format!("refhelper-{}-{}-{:016x}", prefix, process::id(), suffix);
+        let dir = env::temp_dir().join(name);
+        match fs::create_dir(&dir) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|()| dir),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "no free name for a temp dir",
+    ))
+}
+
+// edit text in $VISUAL or $EDITOR (vi by default) as file at path
+pub fn edit_text(text: &str, path: &Path) -> Result<String, Box<dyn Error>> {
+    fs::write(path, text)?;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| String::from("vi"));
+    let mut args = editor.split_whitespace();
+    let program = args.next().ok_or("$EDITOR is empty")?;
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", program, status).into());
+    }
+    Ok(fs::read_to_string(path)?)
+}
+
+// ask a yes or no question, yes by default
+pub fn confirm(question: &str) -> bool {
+    // nobody to answer when commands come from a script or pipe
+    if !io::stdin().is_terminal() {
+        return false;
+    }
+    print!("{} [Y/n] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => false,
+        Ok(_) => !answer.trim().to_lowercase().starts_with('n'),
+    }
+}
+
+// long text goes through a pager when printed to a terminal,
+// `pager` of config or $PAGER or less, an empty `pager` turns it off
+pub fn page(text: &str) {
+    let height = terminal::size().map_or(usize::MAX, |(_, h)| h as usize);
+    if !io::stdout().is_terminal() || text.lines().count() < height {
+        return print!("{}", text);
+    }
+    let pager = match &config::get().pager {
+        Some(p) => p.clone(),
+        None => env::var("PAGER").unwrap_or_else(|_| String::from("less")),
+    };
+    let mut args = pager.split_whitespace();
+    let child = args.next().map(|program| {
+        process::Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()
+    });
+    match child {
+        Some(Ok(mut child)) => {
+            if let Some(mut stdin) = child.stdin.take() {
+                // the pager may quit before reading all
+                let _ = stdin.write_all(text.as_bytes());
+            }
+            let _ = child.wait();
+        }
+        _ => print!("{}", text),
+    }
+}
+
+pub fn print_text_matches(matches: &mut dyn Iterator<Item = (usize, &Entry, TextMatch)>) {
     let mut table = Table::new();
     table.set_content_arrangement(ContentArrangement::Dynamic);
-    table.set_header(vec!["id", "name", "title", "doi", "path"]);
-    for (id, entry) in entries {
+    table.set_header(vec!["id", "name", "page", "context"]);
+    for (id, entry, m) in matches {
         table.add_row(vec![
             &id.to_string(),
             &entry.name,
-            &entry.title,
-            &entry.doi,
-            &match entry.path {
-                Some(_) => "y".to_string(),
-                None => "n".to_string(),
-            },
+            &m.page.to_string(),
+            &m.snippet,
         ]);
     }
     println!("{}", table);
 }
 
-// simple search, no fuzzy, count as score
-pub trait Matcher {
-    fn score(&self, choice: &str, pattern: &str) -> Option<i64>;
-}
-impl Matcher for SkimMatcherV2 {
-    fn score(&self, choice: &str, pattern: &str) -> Option<i64> {
-        self.fuzzy_match(choice, pattern)
-    }
-}
-pub struct StrictMatcher;
-impl Matcher for StrictMatcher {
-    fn score(&self, choice: &str, pattern: &str) -> Option<i64> {
-        match choice.matches(pattern).count() {
-            0 => None,
-            x => Some(x as i64),
-        }
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_create_temp_dir() {
+        let a = create_temp_dir("test").unwrap();
+        let b = create_temp_dir("test").unwrap();
+        assert_ne!(a, b);
+        fs::remove_dir(a).unwrap();
+        fs::remove_dir(b).unwrap();
     }
 }
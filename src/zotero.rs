@@ -0,0 +1,470 @@
+// import items of a Zotero library from its `zotero.sqlite`,
+// the database is copied first since Zotero keeps it locked while running
+use biblatex::{Chunk, EntryType};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::attachment::{Attachment, AttachmentKind};
+use crate::config;
+use crate::lint;
+use crate::utils;
+use crate::Entry;
+
+// Zotero field names to bibtex fields, type specific ones are handled in `bibtex`
+const FIELDS: &[(&str, &str)] = &[
+    ("title", "title"),
+    ("volume", "volume"),
+    ("issue", "number"),
+    ("reportNumber", "number"),
+    ("pages", "pages"),
+    ("publisher", "publisher"),
+    ("place", "address"),
+    ("DOI", "doi"),
+    ("url", "url"),
+    ("ISBN", "isbn"),
+    ("ISSN", "issn"),
+    ("abstractNote", "abstract"),
+    ("series", "series"),
+    ("edition", "edition"),
+    ("university", "school"),
+    ("institution", "institution"),
+    ("language", "language"),
+];
+
+pub fn read_zotero(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    // files of Zotero are kept in `storage/` next to the database
+    let data_dir = path
+        .canonicalize()?
+        .parent()
+        .map_or_else(|| PathBuf::from("."), |p| p.to_path_buf());
+    let dir = utils::create_temp_dir("zotero")?;
+    let entries = copy_database(path, &dir).and_then(|copy| {
+        // the copy is written when changes of the log are applied
+        let conn = Connection::open_with_flags(&copy, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+        read_items(&conn, &data_dir)
+    });
+    // removed whether reading failed or not, errors of reading come first
+    let removed = fs::remove_dir_all(&dir);
+    let entries = entries?;
+    removed?;
+    Ok(entries)
+}
+
+// the database with its write-ahead log, where a running Zotero keeps recent changes
+fn copy_database(path: &Path, dir: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let copy = dir.join("zotero.sqlite");
+    fs::copy(path, &copy)?;
+    for suffix in ["-wal", "-shm"] {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(suffix);
+        let file = PathBuf::from(name);
+        if file.exists() {
+            fs::copy(file, dir.join(format!("zotero.sqlite{}", suffix)))?;
+        }
+    }
+    Ok(copy)
+}
+
+struct Item {
+    kind: String,
+    fields: HashMap<String, String>,
+    creators: Vec<(String, String, bool, String)>, // first, last, single field, creator type
+}
+
+fn read_items(conn: &Connection, data_dir: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let collections = collection_paths(conn)?;
+    let mut stmt = conn.prepare(
+        "SELECT i.itemID, t.typeName FROM items i JOIN itemTypes t USING (itemTypeID)
+         WHERE t.typeName NOT IN ('attachment', 'note', 'annotation')
+         AND i.itemID NOT IN (SELECT itemID FROM deletedItems) ORDER BY i.itemID",
+    )?;
+    let items = stmt
+        .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut used = HashSet::new();
+    let mut entries = Vec::new();
+    for (id, kind) in items {
+        let item = Item {
+            kind,
+            fields: fields(conn, id)?,
+            creators: query(
+                conn,
+                "SELECT c.firstName, c.lastName, c.fieldMode, t.creatorType FROM itemCreators ic
+                 JOIN creators c USING (creatorID) JOIN creatorTypes t USING (creatorTypeID)
+                 WHERE ic.itemID = ?1 ORDER BY ic.orderIndex",
+                id,
+                |r| Ok((r.get(0)?, r.get(1)?, r.get::<_, i64>(2)? == 1, r.get(3)?)),
+            )?,
+        };
+        // tags and collections, e.g. `Reading/Networks`, are keywords
+        let mut keywords: Vec<String> = query(
+            conn,
+            "SELECT t.name FROM itemTags it JOIN tags t USING (tagID) WHERE it.itemID = ?1 ORDER BY t.name",
+            id,
+            |r| r.get(0),
+        )?;
+        let ids: Vec<i64> = query(
+            conn,
+            "SELECT collectionID FROM collectionItems WHERE itemID = ?1",
+            id,
+            |r| r.get(0),
+        )?;
+        keywords.extend(ids.iter().filter_map(|c| collections.get(c).cloned()));
+
+        let mut bib = bibtex(&item, &keywords);
+        let key = match citation_key(&item) {
+            Some(k) => k,
+            None => match lint::citekey(&bib, config::get().citekey_template()) {
+                k if k.is_empty() => format!("zotero{}", id),
+                k => k,
+            },
+        };
+        let key = match used.contains(&key) {
+            true => lint::unique_citekey(&key, &used),
+            false => key,
+        };
+        used.insert(key.clone());
+        bib.key = key.clone();
+
+        let doi = item
+            .fields
+            .get("DOI")
+            .cloned()
+            .unwrap_or_else(|| arxiv_id(&item));
+        let mut entry = Entry::new(&key, &doi);
+        entry.title = item.fields.get("title").cloned().unwrap_or_default();
+        entry.bibtex = bib.to_bibtex_string();
+        let notes: Vec<String> = query(
+            conn,
+            "SELECT note FROM itemNotes WHERE parentItemID = ?1
+             AND itemID NOT IN (SELECT itemID FROM deletedItems) ORDER BY itemID",
+            id,
+            |r| r.get(0),
+        )?;
+        entry.note = notes
+            .iter()
+            .map(|n| note_text(n))
+            .collect::<Vec<_>>()
+            .join("\n");
+        for (kind, target) in attachments(conn, id, data_dir)? {
+            entry.attach(Attachment::new(kind, &target));
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// rows of a query with one item id parameter
+fn query<T, F>(conn: &Connection, sql: &str, id: i64, f: F) -> Result<Vec<T>, Box<dyn Error>>
+where
+    F: FnMut(&rusqlite::Row<'_>) -> rusqlite::Result<T>,
+{
+    let mut stmt = conn.prepare_cached(sql)?;
+    let rows = stmt.query_map(params![id], f)?;
+    Ok(rows.collect::<Result<Vec<T>, _>>()?)
+}
+
+fn fields(conn: &Connection, id: i64) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let fields = query(
+        conn,
+        "SELECT f.fieldName, v.value FROM itemData d JOIN fields f USING (fieldID)
+         JOIN itemDataValues v USING (valueID) WHERE d.itemID = ?1",
+        id,
+        |r| Ok((r.get(0)?, r.get(1)?)),
+    )?;
+    Ok(fields.into_iter().collect())
+}
+
+// full paths of collections, e.g. `Reading/Networks`
+fn collection_paths(conn: &Connection) -> Result<HashMap<i64, String>, Box<dyn Error>> {
+    let mut stmt =
+        conn.prepare("SELECT collectionID, collectionName, parentCollectionID FROM collections")?;
+    let collections: HashMap<i64, (String, Option<i64>)> = stmt
+        .query_map([], |r| Ok((r.get(0)?, (r.get(1)?, r.get(2)?))))?
+        .collect::<Result<_, _>>()?;
+    let mut paths = HashMap::new();
+    for id in collections.keys() {
+        let mut names = Vec::new();
+        let mut next = Some(*id);
+        // bounded, in case of a broken parent cycle
+        while let Some((name, parent)) = next.and_then(|i| collections.get(&i)) {
+            if names.len() > collections.len() {
+                break;
+            }
+            names.push(name.as_str());
+            next = *parent;
+        }
+        names.reverse();
+        paths.insert(*id, names.join("/"));
+    }
+    Ok(paths)
+}
+
+// kind and path or url of files attached to an item
+fn attachments(
+    conn: &Connection,
+    id: i64,
+    data_dir: &Path,
+) -> Result<Vec<(AttachmentKind, String)>, Box<dyn Error>> {
+    let rows: Vec<(i64, String, i64, String, String)> = query(
+        conn,
+        "SELECT a.itemID, i.key, a.linkMode, IFNULL(a.contentType, ''), IFNULL(a.path, '') FROM itemAttachments a JOIN items i USING (itemID)
+         WHERE a.parentItemID = ?1 AND a.itemID NOT IN (SELECT itemID FROM deletedItems) ORDER BY a.itemID",
+        id,
+        |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?, r.get(4)?)),
+    )?;
+    let mut attachments = Vec::new();
+    for (item_id, key, mode, content_type, path) in rows {
+        let kind = match content_type.as_str() {
+            "application/pdf" => AttachmentKind::Pdf,
+            _ => AttachmentKind::Supplement,
+        };
+        // 0, 1: stored files, 2: linked files, 3: linked urls
+        let target = match mode {
+            0 | 1 => match path.strip_prefix("storage:") {
+                Some(name) => data_dir.join("storage").join(key).join(name),
+                None => continue,
+            },
+            2 if path.starts_with("attachments:") => {
+                println!(
+                    "'{}' is relative to the Zotero base directory, not imported",
+                    path
+                );
+                continue;
+            }
+            2 => PathBuf::from(path),
+            3 => match fields(conn, item_id)?.remove("url") {
+                Some(url) => {
+                    attachments.push((kind, url));
+                    continue;
+                }
+                None => continue,
+            },
+            _ => continue,
+        };
+        attachments.push((kind, target.display().to_string()));
+    }
+    Ok(attachments)
+}
+
+fn bibtex(item: &Item, keywords: &[String]) -> biblatex::Entry {
+    let f = |name: &str| item.fields.get(name).filter(|v| !v.is_empty());
+    let thesis = f("thesisType").map_or("", |t| t.as_str()).to_lowercase();
+    let (entry_type, container) = match item.kind.as_str() {
+        "journalArticle" | "magazineArticle" | "newspaperArticle" => ("article", "journal"),
+        "conferencePaper" => ("inproceedings", "booktitle"),
+        "bookSection" => ("incollection", "booktitle"),
+        "book" => ("book", ""),
+        "thesis" if thesis.contains("master") => ("mastersthesis", ""),
+        "thesis" => ("phdthesis", ""),
+        "report" => ("techreport", ""),
+        _ => ("misc", "howpublished"),
+    };
+    let mut bib = biblatex::Entry::new(String::new(), EntryType::new(entry_type));
+    let mut set = |field: &str, value: &str| {
+        if !value.is_empty() && bib.get(field).is_none() {
+            bib.set(field, vec![Chunk::Normal(value.to_owned())]);
+        }
+    };
+    for (name, field) in FIELDS.iter() {
+        if let Some(v) = f(name) {
+            set(field, v);
+        }
+    }
+    if !container.is_empty() {
+        for name in [
+            "publicationTitle",
+            "proceedingsTitle",
+            "bookTitle",
+            "websiteTitle",
+        ]
+        .iter()
+        {
+            if let Some(v) = f(name) {
+                set(container, v);
+            }
+        }
+    }
+    // stored as `2013-08-12 August 12, 2013`, unknown parts are `00`
+    let date: Vec<&str> = f("date")
+        .and_then(|d| d.split_whitespace().next())
+        .map_or(vec![], |d| d.split('-').collect());
+    if let Some(year) = date.first().filter(|y| **y != "0000") {
+        set("year", year);
+    }
+    if let Some(month) = date
+        .get(1)
+        .and_then(|m| m.parse::<u8>().ok())
+        .filter(|m| *m > 0)
+    {
+        set("month", &month.to_string());
+    }
+    let arxiv = arxiv_id(item);
+    if !arxiv.is_empty() {
+        set("eprint", &arxiv);
+        set("archiveprefix", "arXiv");
+    }
+    set("keywords", &keywords.join(", "));
+
+    for role in ["author", "editor"].iter() {
+        let mut chunks = Vec::new();
+        for (first, last, single, kind) in item.creators.iter() {
+            // contributors of other roles are listed as authors
+            let is_role = kind == role || (*role == "author" && kind != "editor");
+            if !is_role {
+                continue;
+            }
+            if !chunks.is_empty() {
+                chunks.push(Chunk::Normal(String::from(" and ")));
+            }
+            match (single, first.is_empty()) {
+                // institutions are a single field, kept as is
+                (true, _) => chunks.push(Chunk::Verbatim(last.clone())),
+                (false, true) => chunks.push(Chunk::Normal(last.clone())),
+                (false, false) => chunks.push(Chunk::Normal(format!("{}, {}", last, first))),
+            }
+        }
+        if !chunks.is_empty() {
+            bib.set(role, chunks);
+        }
+    }
+    bib
+}
+
+// citekey set by Better BibTeX, a `Citation Key: smith2013` line in extra
+fn citation_key(item: &Item) -> Option<String> {
+    let extra = item.fields.get("extra")?;
+    extra
+        .lines()
+        .find_map(|l| l.strip_prefix("Citation Key:"))
+        .map(|k| k.trim().to_owned())
+        .filter(|k| !k.is_empty())
+}
+
+// arXiv id of preprints, e.g. archiveID `arXiv:1904.12901`
+fn arxiv_id(item: &Item) -> String {
+    let from_extra = item.fields.get("extra").and_then(|e| {
+        e.lines()
+            .find_map(|l| l.strip_prefix("arXiv:"))
+            .map(|id| id.trim().to_owned())
+    });
+    item.fields
+        .get("archiveID")
+        .and_then(|id| id.strip_prefix("arXiv:"))
+        .map(|id| id.trim().to_owned())
+        .or(from_extra)
+        .unwrap_or_default()
+}
+
+// notes are HTML, paragraphs become lines
+fn note_text(html: &str) -> String {
+    let html = html
+        .replace("</p>", "</p>\n")
+        .replace("<br>", "\n")
+        .replace("<br/>", "\n");
+    let fragment = scraper::Html::parse_fragment(&html);
+    let text: String = fragment.root_element().text().collect();
+    text.lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use biblatex::ChunksExt;
+
+    #[test]
+    fn test_copy_database() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("refhelper-wal-{}", std::process::id()));
+        fs::create_dir_all(dir.join("copy"))?;
+        let path = dir.join("zotero.sqlite");
+        // changes stay in the log while Zotero is running
+        let conn = Connection::open(&path)?;
+        conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+        conn.execute_batch(
+            "PRAGMA wal_autocheckpoint = 0;
+            CREATE TABLE items (itemID INTEGER PRIMARY KEY);
+            INSERT INTO items VALUES (1);",
+        )?;
+        let copy = Connection::open(copy_database(&path, &dir.join("copy"))?)?;
+        let n: i64 = copy.query_row("SELECT COUNT(*) FROM items", [], |r| r.get(0))?;
+        assert_eq!(n, 1);
+        // tables of items are missing, the temp dir is removed all the same
+        assert!(read_zotero(&path).is_err());
+        let prefix = format!("refhelper-zotero-{}-", std::process::id());
+        let left = fs::read_dir(std::env::temp_dir())?
+            .filter_map(|e| e.ok())
+            .any(|e| e.file_name().to_string_lossy().starts_with(&prefix));
+        assert!(!left);
+        drop(conn);
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_items() -> Result<(), Box<dyn Error>> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(
+            "CREATE TABLE itemTypes (itemTypeID INTEGER PRIMARY KEY, typeName TEXT);
+            CREATE TABLE items (itemID INTEGER PRIMARY KEY, itemTypeID INT, key TEXT);
+            CREATE TABLE deletedItems (itemID INTEGER PRIMARY KEY);
+            CREATE TABLE fields (fieldID INTEGER PRIMARY KEY, fieldName TEXT);
+            CREATE TABLE itemDataValues (valueID INTEGER PRIMARY KEY, value);
+            CREATE TABLE itemData (itemID INT, fieldID INT, valueID INT);
+            CREATE TABLE creators (creatorID INTEGER PRIMARY KEY, firstName TEXT, lastName TEXT, fieldMode INT);
+            CREATE TABLE creatorTypes (creatorTypeID INTEGER PRIMARY KEY, creatorType TEXT);
+            CREATE TABLE itemCreators (itemID INT, creatorID INT, creatorTypeID INT, orderIndex INT);
+            CREATE TABLE collections (collectionID INTEGER PRIMARY KEY, collectionName TEXT, parentCollectionID INT);
+            CREATE TABLE collectionItems (collectionID INT, itemID INT);
+            CREATE TABLE tags (tagID INTEGER PRIMARY KEY, name TEXT);
+            CREATE TABLE itemTags (itemID INT, tagID INT);
+            CREATE TABLE itemNotes (itemID INTEGER PRIMARY KEY, parentItemID INT, note TEXT);
+            CREATE TABLE itemAttachments (itemID INTEGER PRIMARY KEY, parentItemID INT,
+                linkMode INT, contentType TEXT, path TEXT);
+            INSERT INTO itemTypes VALUES (1, 'conferencePaper'), (2, 'attachment'), (3, 'note');
+            INSERT INTO items VALUES (1, 1, 'AAAA'), (2, 2, 'BBBB'), (3, 3, 'CCCC'), (4, 1, 'DDDD');
+            INSERT INTO deletedItems VALUES (4);
+            INSERT INTO fields VALUES (1, 'title'), (2, 'proceedingsTitle'), (3, 'date'), (4, 'DOI');
+            INSERT INTO itemDataValues VALUES (1, 'Achieving high utilization with software-driven WAN'),
+                (2, 'Proceedings of SIGCOMM'), (3, '2013-08-12 August 12, 2013'), (4, '10.1145/2486001.2486012');
+            INSERT INTO itemData VALUES (1, 1, 1), (1, 2, 2), (1, 3, 3), (1, 4, 4);
+            INSERT INTO creators VALUES (1, 'Chi-Yao', 'Hong', 0), (2, '', 'Microsoft', 1);
+            INSERT INTO creatorTypes VALUES (1, 'author');
+            INSERT INTO itemCreators VALUES (1, 1, 1, 0), (1, 2, 1, 1);
+            INSERT INTO collections VALUES (1, 'Reading', NULL), (2, 'Networks', 1);
+            INSERT INTO collectionItems VALUES (2, 1);
+            INSERT INTO tags VALUES (1, 'sdn');
+            INSERT INTO itemTags VALUES (1, 1);
+            INSERT INTO itemNotes VALUES (3, 1, '<div><p>Good &amp; short</p><p>Read again</p></div>');
+            INSERT INTO itemAttachments VALUES (2, 1, 0, 'application/pdf', 'storage:swan.pdf');",
+        )?;
+        let entries = read_items(&conn, Path::new("/zotero"))?;
+        assert_eq!(entries.len(), 1);
+        let e = &entries[0];
+        assert_eq!(e.name, "hong2013achieving");
+        assert_eq!(e.doi, "10.1145/2486001.2486012");
+        assert_eq!(e.note, "Good & short\nRead again");
+        assert_eq!(e.pdf(), Some(Path::new("/zotero/storage/BBBB/swan.pdf")));
+        let bib = e.parsed().unwrap();
+        assert_eq!(bib.entry_type, EntryType::InProceedings);
+        assert_eq!(
+            bib.get("booktitle").unwrap().format_verbatim(),
+            "Proceedings of SIGCOMM"
+        );
+        assert_eq!(
+            bib.get("keywords").unwrap().format_verbatim(),
+            "sdn, Reading/Networks"
+        );
+        let authors = bib.author().unwrap();
+        assert_eq!(authors.len(), 2);
+        assert_eq!(authors[1].name, "Microsoft");
+        Ok(())
+    }
+}